@@ -0,0 +1,88 @@
+//! Print how the settings saved in two PNGs exported by hitomezashi differ: which seeds,
+//! spacings, colors and so on changed, and which selectors were flipped.
+//!
+//! Usage: `cargo run --example diff_patterns -- old.png new.png`
+
+use std::{env, error::Error, path::Path, process};
+
+use hitomezashi::RectSettings;
+use serde_json::Value;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let [old_path, new_path] = &args[..] else {
+        eprintln!("Usage: diff_patterns <old png> <new png>");
+        process::exit(2);
+    };
+
+    let (old, new) = (load(old_path)?, load(new_path)?);
+    // Compare the settings as they would be saved now, so that older files, which may be
+    // missing fields or use older names for them, line up with newer ones
+    let (Value::Object(old_fields), Value::Object(new_fields)) =
+        (serde_json::to_value(&old)?, serde_json::to_value(&new)?)
+    else {
+        unreachable!("The settings are always saved as a JSON object");
+    };
+
+    let mut differences = 0;
+    for (key, old_value) in &old_fields {
+        let new_value = &new_fields[key];
+        if old_value == new_value {
+            continue;
+        }
+        differences += 1;
+        match key.as_str() {
+            "horz_selectors" | "vert_selectors" => {
+                println!("{key}: {}", selector_diff(old_value, new_value));
+            }
+            // These are saved as a number of steps, so show them as the chances they stand for
+            "horz_bias" => println!("{key}: {} -> {}", old.horz_bias, new.horz_bias),
+            "vert_bias" => println!("{key}: {} -> {}", old.vert_bias, new.vert_bias),
+            _ => println!("{key}: {old_value} -> {new_value}"),
+        }
+    }
+    if differences == 0 {
+        println!("The settings are the same");
+    }
+    Ok(())
+}
+
+/// Read the settings saved in the PNG at `path`, exiting if it doesn't have any
+fn load(path: &str) -> Result<RectSettings, Box<dyn Error>> {
+    match hitomezashi::read_settings_chunk(Path::new(path))? {
+        Some(json) => Ok(RectSettings::from_json(&json)?),
+        None => {
+            eprintln!("{path} has no Settings chunk");
+            process::exit(1);
+        }
+    }
+}
+
+/// Which of the selectors in the JSON arrays `old` and `new` differ, and whether one
+/// has more of them than the other
+fn selector_diff(old: &Value, new: &Value) -> String {
+    let as_bools = |value: &Value| -> Vec<bool> {
+        value
+            .as_array()
+            .map(|selectors| selectors.iter().filter_map(Value::as_bool).collect())
+            .unwrap_or_default()
+    };
+    let (old, new) = (as_bools(old), as_bools(new));
+
+    let flipped: Vec<_> = old
+        .iter()
+        .zip(&new)
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(index, _)| index.to_string())
+        .collect();
+    let mut diff = if flipped.is_empty() {
+        "none flipped".to_string()
+    } else {
+        format!("{} flipped, at {}", flipped.len(), flipped.join(", "))
+    };
+    if old.len() != new.len() {
+        diff.push_str(&format!(", {} -> {} selectors", old.len(), new.len()));
+    }
+    diff
+}
@@ -0,0 +1,25 @@
+//! Print the settings saved in a PNG exported by hitomezashi, whether they are in a plain
+//! tEXt chunk or were saved compressed by another tool.
+//!
+//! Usage: `cargo run --example read_metadata -- path/to/pattern.png`
+
+use std::{env, error::Error, path::Path, process};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("Usage: read_metadata <png>");
+        process::exit(2);
+    };
+
+    match hitomezashi::read_settings_chunk(Path::new(&path))? {
+        Some(json) => {
+            let value: serde_json::Value = serde_json::from_str(&json)?;
+            println!("{}", serde_json::to_string_pretty(&value)?);
+        }
+        None => {
+            eprintln!("{path} has no Settings chunk");
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
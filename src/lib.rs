@@ -0,0 +1,5006 @@
+//! The hitomezashi pattern itself: the settings that describe a pattern, where its
+//! stitches go, and rendering it to an RGBA buffer, PNG metadata, or SVG. Nothing here
+//! depends on nannou, so patterns can be generated without opening a window.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{BufWriter, Read, Write},
+    ops::{Range, RangeInclusive},
+    path::Path,
+};
+
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use image::codecs::jpeg::JpegEncoder;
+use rand::Rng;
+use rand_pcg::Pcg64;
+use rand_seeder::Seeder;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+mod test_vectors;
+
+/// The font used to draw text into exported images
+static CAPTION_FONT: &[u8] = include_bytes!("../assets/DejaVuSansMono.ttf");
+
+/// The height in pixels of the legend strip below exported patterns
+const LEGEND_HEIGHT: u32 = 48;
+
+/// How long, in bytes, the settings JSON saved in a PNG can be before it is compressed.
+/// Long hand-edited selectors would otherwise add several KB of text to every export.
+const COMPRESSED_SETTINGS_LEN: usize = 1024;
+
+/// The version of the `.hito` file format that `write_hito` saves. Files with a newer
+/// version may have changed in ways this version can't read, so they are rejected.
+/// Version 2 saves the biases as whole steps (see `BIAS_STEPS`) instead of fractions.
+pub const HITO_VERSION: u32 = 2;
+
+/// How wide the lines between the tiles of a contact sheet are, and their color
+const SHEET_SEPARATOR: u32 = 2;
+const SHEET_SEPARATOR_COLOR: Rgb = Rgb::new(160, 160, 160);
+
+/// How many rows of an image are drawn together when rendering in parallel
+const BAND_ROWS: u32 = 32;
+
+/// The smallest gap between lines, in pixels. The pattern is never drawn with its lines any
+/// closer, since a gap of 0 would leave the drawing loops stuck on the same line forever.
+pub const MIN_SPACING: f32 = 1.0;
+
+/// The most dashes each cell can be split into (see `RectSettings::dashes_per_cell`)
+pub const MAX_DASHES_PER_CELL: u32 = 8;
+
+/// The colors the loops of a pattern drawn with `color_by_loop` take, in turn
+pub const LOOP_PALETTE: [Rgb; 8] = [
+    Rgb::new(31, 119, 180),
+    Rgb::new(255, 127, 14),
+    Rgb::new(44, 160, 44),
+    Rgb::new(214, 39, 40),
+    Rgb::new(148, 103, 189),
+    Rgb::new(140, 86, 75),
+    Rgb::new(227, 119, 194),
+    Rgb::new(23, 190, 207),
+];
+
+/// The least contrast ratio (see `Rgb::contrast_ratio`) that stitches should have with the
+/// background to be easy to see, which is what WCAG asks of graphics
+pub const MIN_CONTRAST_RATIO: f64 = 3.0;
+
+/// How finely the biases are divided: each is a whole number of 1/`BIAS_STEPS` steps
+pub const BIAS_STEPS: u32 = 256;
+
+/// How many selectors are generated from a seed until `RectSettings::fit_selectors` is
+/// told the size of the pattern
+const SELECTOR_COUNT: usize = 10;
+
+/// An 8-bit sRGB color, saved as an `[r, g, b]` array
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "[u8; 3]", into = "[u8; 3]")]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(255, 255, 255);
+
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Rgb { red, green, blue }
+    }
+
+    /// The color `t` of the way from `self` (at 0.0) to `other` (at 1.0)
+    pub fn lerp(self, other: Rgb, t: f32) -> Rgb {
+        let channel =
+            |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+        Rgb::new(
+            channel(self.red, other.red),
+            channel(self.green, other.green),
+            channel(self.blue, other.blue),
+        )
+    }
+
+    /// The opposite color, with every channel flipped, so black and white swap
+    pub fn inverted(self) -> Rgb {
+        Rgb::new(255 - self.red, 255 - self.green, 255 - self.blue)
+    }
+
+    /// How bright the color looks, from 0.0 for black to 255.0 for white
+    pub fn luma(self) -> f32 {
+        0.299 * self.red as f32 + 0.587 * self.green as f32 + 0.114 * self.blue as f32
+    }
+
+    /// How much light the color gives off, from 0.0 for black to 1.0 for white, as WCAG
+    /// defines it
+    pub fn relative_luminance(self) -> f64 {
+        let linear = |channel: u8| {
+            let channel = channel as f64 / 255.0;
+            if channel <= 0.04045 {
+                channel / 12.92
+            } else {
+                ((channel + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * linear(self.red) + 0.7152 * linear(self.green) + 0.0722 * linear(self.blue)
+    }
+
+    /// How well the color stands out from `other`, from 1.0 for the same brightness to 21.0
+    /// for black against white, as WCAG defines it. Either color can be the brighter one.
+    pub fn contrast_ratio(self, other: Rgb) -> f64 {
+        let (a, b) = (self.relative_luminance(), other.relative_luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// Whether the color is a shade of gray, so it can be saved as one without changing
+    pub fn is_gray(self) -> bool {
+        self.red == self.green && self.green == self.blue
+    }
+
+    /// Black or white, whichever stands out more against `self`
+    pub fn contrasting(self) -> Rgb {
+        if self.luma() < 128.0 {
+            Rgb::WHITE
+        } else {
+            Rgb::BLACK
+        }
+    }
+}
+
+impl From<[u8; 3]> for Rgb {
+    fn from([red, green, blue]: [u8; 3]) -> Self {
+        Rgb::new(red, green, blue)
+    }
+}
+
+impl From<Rgb> for [u8; 3] {
+    fn from(color: Rgb) -> Self {
+        [color.red, color.green, color.blue]
+    }
+}
+
+/// Everything that describes a pattern. This is what is saved with exported patterns
+/// (see `to_json`), so fields that are missing from older saves take their defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RectSettings {
+    /// The distance between horizontal lines, which is also the dash length along the
+    /// vertical lines
+    pub horz_spacing: f32,
+    /// The distance between vertical lines, which is also the dash length along the
+    /// horizontal lines
+    pub vert_spacing: f32,
+    /// If set, every other gap between lines on a square grid is this wide instead, so the
+    /// rows and columns alternate between wide and narrow (see `line_gaps`)
+    pub spacing_alt: Option<f32>,
+    /// Whether to round the spacings to whole pixels, so that every cell, and so every dash
+    /// of an exported image, is the same number of pixels long. The lines then sit a little
+    /// away from where the spacings put them, by up to half a pixel per line.
+    pub snap_to_pixels: bool,
+    /// If set, exactly this many selectors are generated for each axis, so the pattern
+    /// repeats every that many lines whatever its size. Otherwise there is one selector per
+    /// line (see `fit_selectors`).
+    pub selector_len: Option<usize>,
+    pub horz_selectors: Vec<bool>,
+    pub vert_selectors: Vec<bool>,
+    pub horz_seed: u64,
+    pub vert_seed: u64,
+    /// The seed for the third family of lines of a triangular grid, the ones running down
+    /// to the left. Their selectors are always generated from it.
+    pub diag_seed: u64,
+    #[serde(skip)]
+    pub diag_selectors: Vec<bool>,
+    /// The chance (0 to 1) of each generated horizontal selector being true. It is rounded
+    /// to a whole number of `BIAS_STEPS` when generating, and saved as that number, so the
+    /// same bias always gives the same selectors.
+    #[serde(with = "bias_steps")]
+    pub horz_bias: f64,
+    /// The chance (0 to 1) of each generated vertical selector being true
+    #[serde(with = "bias_steps")]
+    pub vert_bias: f64,
+    /// How many horizontal selectors to generate from the seed, normally one per
+    /// horizontal line (see `fit_selectors`)
+    #[serde(skip)]
+    pub horz_count: usize,
+    /// How many vertical selectors to generate from the seed, normally one per vertical
+    /// line
+    #[serde(skip)]
+    pub vert_count: usize,
+    /// How many selectors to generate from `diag_seed`
+    #[serde(skip)]
+    pub diag_count: usize,
+    /// If not empty, the horizontal selectors come from this text instead of the seed
+    /// (see `selectors_from_text`)
+    pub horz_text: String,
+    /// If not empty, the vertical selectors come from this text instead of the seed
+    pub vert_text: String,
+    /// Whether to draw a solid outline around the edge of the canvas, on top of the pattern
+    pub draw_border: bool,
+    pub border_color: Rgb,
+    /// How thick the border is, in pixels. Older files call this `border_weight`.
+    #[serde(alias = "border_weight")]
+    pub border_width: f32,
+    /// Whether to write a text caption into a corner of exported images
+    pub draw_caption: bool,
+    /// The caption to write. If empty, a description of the seeds and spacing is used
+    pub caption_text: String,
+    pub caption_corner: Corner,
+    pub caption_size: f32,
+    pub caption_color: Rgb,
+    /// Whether to adjust the export size to a whole number of cells, and in which direction
+    pub grid_lock: GridLock,
+    /// The shape to pad exported PNGs out to, so they aren't stretched when shown at it
+    pub aspect_ratio: AspectRatio,
+    /// What kind of pixels exported PNGs are saved with (see `PngColor`)
+    pub png_color: PngColor,
+    /// The size of the window, in points, when the pattern was saved as a PNG, so that
+    /// loading it can put the window back to that size and show the same lines. These are
+    /// unset for patterns saved without a window, and in PNGs from older versions.
+    pub window_width: Option<u32>,
+    pub window_height: Option<u32>,
+    /// The resolution exported PNGs say they should be printed at, in dots per inch
+    pub dpi: u32,
+    /// The color behind the pattern, in the window and in exported images
+    pub background_color: Rgb,
+    /// The color of the stitches along the horizontal lines
+    pub horz_color: Rgb,
+    /// The color of the stitches along the vertical lines
+    pub vert_color: Rgb,
+    /// Whether the stitches fade from `color_start` to `color_end` along their lines, from
+    /// left to right along the horizontal lines and from top to bottom along the vertical
+    /// ones, instead of using the colors for each axis
+    pub gradient: bool,
+    /// Whether each loop of a square grid (see `Pattern::dash_loops`) is drawn in its own
+    /// color from `LOOP_PALETTE`, instead of the colors for each axis or the gradient. SVGs
+    /// still use the colors for each axis.
+    pub color_by_loop: bool,
+    pub color_start: Rgb,
+    pub color_end: Rgb,
+    /// The thickness of the stitches
+    pub line_weight: f32,
+    /// Whether to blend the edges of the stitches in exported images, by how much of each
+    /// pixel they cover, instead of filling whole pixels
+    pub antialias: bool,
+    /// Whether to round off both ends of each stitch in exported images, by leaving out the
+    /// pixels in its corners. The stitches keep their length, so the gaps between them stay
+    /// the same, but where two stitches meet at a corner of the pattern the outside of the
+    /// corner is rounded instead of square. The live view always draws square ends.
+    pub round_caps: bool,
+    /// How opaque the stitches are, from 0.0 (invisible) to 1.0 (solid). Stitches are
+    /// blended over whatever is under them, such as the layers below.
+    pub opacity: f32,
+    /// How much of each cell a stitch covers, from its start. 1.0 fills the whole cell, so
+    /// each stitch is as long as the gap after it
+    pub dash_ratio: f32,
+    /// How many short dashes each stitched cell is split into, from 1 to
+    /// `MAX_DASHES_PER_CELL`. Each of them covers `dash_ratio` of its share of the cell.
+    pub dashes_per_cell: u32,
+    /// Whether the lines are stitched as hitomezashi dashes. Otherwise every cell is
+    /// stitched in full, which draws the grid the pattern runs along as solid lines.
+    pub dashed: bool,
+    /// Where the vertical selectors come from
+    pub vert_derivation: VertDerivation,
+    /// Whether to make the vertical selectors read the same backwards, so the vertical
+    /// lines on either side of the pattern's center line mirror each other
+    pub mirror_x: bool,
+    /// Whether to make the horizontal selectors read the same backwards, like `mirror_x`
+    pub mirror_y: bool,
+    /// The shape of the grid the stitches run along
+    pub grid: Grid,
+    /// Whether to stagger every other horizontal line by one dash, like courses of bricks
+    pub brick: bool,
+    /// Whether exported patterns should tile seamlessly (see `pattern_size` and `Pattern`)
+    pub tileable: bool,
+    /// How far right the grid is shifted from the left edge, in pixels
+    pub offset_x: f32,
+    /// How far down the grid is shifted from the top edge, in pixels
+    pub offset_y: f32,
+    /// How wide a strip of background to leave around the pattern, in whole pixels, so it
+    /// sits inside a frame. The stitches stay inside it, and the offsets are measured from
+    /// its inside edge. Tileable patterns have no margin, since they have to reach their edges
+    /// to line up with the next tile.
+    pub margin: f32,
+    /// Whether to suggest a file name built from `file_name_template` when exporting
+    pub auto_name: bool,
+    /// The file name to suggest when exporting. `{horz_seed}`, `{vert_seed}`, and
+    /// `{spacing}` are replaced with the current settings (see `spacing_label`)
+    pub file_name_template: String,
+    /// Whether to add a strip below exported patterns listing the seeds and spacing
+    pub draw_legend: bool,
+    /// Set when a selector has been changed by hand rather than generated from a seed
+    #[serde(skip)]
+    pub edited: bool,
+    /// Set whenever the selectors change, so the view knows it needs to be redrawn
+    #[serde(skip)]
+    pub dirty: bool,
+}
+
+impl Default for RectSettings {
+    fn default() -> Self {
+        RectSettings::new(25.0)
+    }
+}
+
+impl RectSettings {
+    pub fn new(spacing: f32) -> Self {
+        let mut settings = RectSettings {
+            horz_spacing: spacing,
+            vert_spacing: spacing,
+            snap_to_pixels: false,
+            spacing_alt: None,
+            selector_len: None,
+            horz_selectors: Vec::new(),
+            vert_selectors: Vec::new(),
+            horz_seed: 0,
+            vert_seed: 0,
+            diag_seed: 0,
+            diag_selectors: Vec::new(),
+            horz_bias: 0.5,
+            vert_bias: 0.5,
+            horz_count: SELECTOR_COUNT,
+            vert_count: SELECTOR_COUNT,
+            diag_count: SELECTOR_COUNT,
+            horz_text: String::new(),
+            vert_text: String::new(),
+            draw_border: false,
+            border_color: Rgb::BLACK,
+            border_width: 5.0,
+            draw_caption: false,
+            caption_text: String::new(),
+            caption_corner: Corner::BottomRight,
+            caption_size: 24.0,
+            caption_color: Rgb::BLACK,
+            grid_lock: GridLock::Off,
+            aspect_ratio: AspectRatio::Window,
+            png_color: PngColor::Rgba,
+            window_width: None,
+            window_height: None,
+            dpi: 96,
+            background_color: Rgb::WHITE,
+            horz_color: Rgb::BLACK,
+            vert_color: Rgb::BLACK,
+            gradient: false,
+            color_by_loop: false,
+            color_start: Rgb::BLACK,
+            color_end: Rgb::new(0, 0, 255),
+            line_weight: 3.0,
+            antialias: false,
+            round_caps: false,
+            opacity: 1.0,
+            dash_ratio: 1.0,
+            dashes_per_cell: 1,
+            dashed: true,
+            vert_derivation: VertDerivation::Seed,
+            mirror_x: false,
+            mirror_y: false,
+            grid: Grid::Square,
+            brick: false,
+            tileable: false,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            margin: 0.0,
+            auto_name: false,
+            file_name_template: "hito_h{horz_seed}_v{vert_seed}_s{spacing}.png".to_string(),
+            draw_legend: false,
+            edited: false,
+            dirty: false,
+        };
+        settings.regenerate_selectors();
+        settings
+    }
+
+    /// Set both spacings. Like the other `with_` methods this only sets the field, so
+    /// finish with `build` to generate the selectors, for example
+    /// `RectSettings::default().with_spacing(30.0).with_horz_seed(7).build()`.
+    pub fn with_spacing(mut self, spacing: f32) -> Self {
+        self.horz_spacing = spacing;
+        self.vert_spacing = spacing;
+        self
+    }
+
+    pub fn with_horz_seed(mut self, seed: u64) -> Self {
+        self.horz_seed = seed;
+        self
+    }
+
+    pub fn with_vert_seed(mut self, seed: u64) -> Self {
+        self.vert_seed = seed;
+        self
+    }
+
+    /// Set the horizontal and vertical seeds
+    pub fn with_seeds(self, horz_seed: u64, vert_seed: u64) -> Self {
+        self.with_horz_seed(horz_seed).with_vert_seed(vert_seed)
+    }
+
+    /// Set both biases
+    pub fn with_bias(mut self, bias: f64) -> Self {
+        self.horz_bias = bias;
+        self.vert_bias = bias;
+        self
+    }
+
+    pub fn with_grid(mut self, grid: Grid) -> Self {
+        self.grid = grid;
+        self
+    }
+
+    pub fn with_line_weight(mut self, line_weight: f32) -> Self {
+        self.line_weight = line_weight;
+        self
+    }
+
+    /// Generate the selectors from the seeds set with the `with_` methods
+    pub fn build(mut self) -> Self {
+        self.regenerate_selectors();
+        self
+    }
+
+    /// The settings as JSON, for storing alongside an exported pattern so it can be
+    /// recreated
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("The settings can always be serialized")
+    }
+
+    /// Rebuild settings from the JSON written by `to_json`. Fields missing from the JSON
+    /// keep their defaults. The single `spacing` and `bias` keys of older saves set both
+    /// spacings and both biases, and biases saved as fractions are read as the nearest step.
+    /// Spacings below `MIN_SPACING` are raised to it. The selectors are used as saved; if
+    /// they don't match what the seeds would generate, the settings are marked as edited by
+    /// hand.
+    pub fn from_json(json: &str) -> Result<Self, LoadError> {
+        Self::from_value(serde_json::from_str(json).map_err(LoadError::Json)?)
+    }
+
+    /// Like `from_json`, for settings that were parsed as part of a larger JSON document
+    pub fn from_value(mut value: serde_json::Value) -> Result<Self, LoadError> {
+        if let Some(fields) = value.as_object_mut() {
+            for (old, new) in [
+                ("spacing", ["horz_spacing", "vert_spacing"]),
+                ("bias", ["horz_bias", "vert_bias"]),
+            ] {
+                if let Some(old) = fields.remove(old) {
+                    for key in new {
+                        fields.entry(key).or_insert_with(|| old.clone());
+                    }
+                }
+            }
+            // Older saves have the biases as fractions rather than whole steps
+            for key in ["horz_bias", "vert_bias"] {
+                if let Some(bias) = fields.get_mut(key).filter(|bias| bias.is_f64()) {
+                    let fraction = bias
+                        .as_f64()
+                        .expect("The bias was just checked to be a float");
+                    *bias = ((fraction * BIAS_STEPS as f64).round() as i64).into();
+                }
+            }
+        }
+        let saved_horz = value.get("horz_selectors").is_some();
+        let saved_vert = value.get("vert_selectors").is_some();
+        let mut settings: RectSettings = serde_json::from_value(value).map_err(LoadError::Json)?;
+
+        for (key, spacing) in [
+            ("horz_spacing", &mut settings.horz_spacing),
+            ("vert_spacing", &mut settings.vert_spacing),
+        ]
+        .into_iter()
+        .chain(
+            settings
+                .spacing_alt
+                .as_mut()
+                .map(|alt| ("spacing_alt", alt)),
+        ) {
+            if spacing.is_nan() {
+                return Err(LoadError::BadField(key));
+            }
+            *spacing = spacing.max(MIN_SPACING);
+        }
+        for (key, bias) in [
+            ("horz_bias", settings.horz_bias),
+            ("vert_bias", settings.vert_bias),
+        ] {
+            if !(0.0..=1.0).contains(&bias) {
+                return Err(LoadError::BadField(key));
+            }
+        }
+        if !(0.0..=1.0).contains(&settings.opacity) {
+            return Err(LoadError::BadField("opacity"));
+        }
+        if settings.selector_len == Some(0) {
+            return Err(LoadError::BadField("selector_len"));
+        }
+        if settings.dpi == 0 {
+            return Err(LoadError::BadField("dpi"));
+        }
+        if !(settings.dash_ratio > 0.0 && settings.dash_ratio <= 1.0) {
+            return Err(LoadError::BadField("dash_ratio"));
+        }
+        if !(1..=MAX_DASHES_PER_CELL).contains(&settings.dashes_per_cell) {
+            return Err(LoadError::BadField("dashes_per_cell"));
+        }
+        for (key, offset) in [
+            ("offset_x", settings.offset_x),
+            ("offset_y", settings.offset_y),
+        ] {
+            if !offset.is_finite() {
+                return Err(LoadError::BadField(key));
+            }
+        }
+
+        // Generate as many selectors as were saved, so they can be compared
+        let horz_selectors = std::mem::take(&mut settings.horz_selectors);
+        let vert_selectors = std::mem::take(&mut settings.vert_selectors);
+        for (key, saved, selectors) in [
+            ("horz_selectors", saved_horz, &horz_selectors),
+            ("vert_selectors", saved_vert, &vert_selectors),
+        ] {
+            if saved && selectors.is_empty() {
+                return Err(LoadError::BadField(key));
+            }
+        }
+        settings.horz_count = horz_selectors.len();
+        settings.vert_count = vert_selectors.len();
+        settings.regenerate_selectors();
+        if saved_horz {
+            settings.horz_selectors = horz_selectors;
+        }
+        if saved_vert {
+            settings.vert_selectors = vert_selectors;
+        }
+        settings.edited = !selectors_match_seed(&settings);
+
+        Ok(settings)
+    }
+
+    /// Generate the selectors from the texts or seeds, deriving the vertical selectors
+    /// from the horizontal ones if `vert_derivation` says to, and mirroring them if
+    /// `mirror_x` or `mirror_y` say to
+    pub fn regenerate_selectors(&mut self) {
+        self.horz_selectors = generate_selectors(
+            &self.horz_text,
+            self.horz_seed,
+            self.horz_bias,
+            self.horz_count,
+            self.mirror_y,
+        );
+
+        match self.vert_derivation {
+            VertDerivation::Seed => {
+                self.vert_selectors = generate_selectors(
+                    &self.vert_text,
+                    self.vert_seed,
+                    self.vert_bias,
+                    self.vert_count,
+                    self.mirror_x,
+                )
+            }
+            derivation => {
+                self.vert_selectors = derivation.derive(&self.horz_selectors);
+                if self.mirror_x {
+                    mirror_selectors(&mut self.vert_selectors);
+                }
+            }
+        }
+        self.regenerate_diag_selectors();
+
+        self.edited = false;
+        self.dirty = true;
+    }
+
+    /// Generate the selectors for the third family of lines of a triangular grid. These
+    /// can't be edited by hand, so they are regenerated whenever their count changes.
+    fn regenerate_diag_selectors(&mut self) {
+        self.diag_selectors = generate_selectors("", self.diag_seed, 0.5, self.diag_count, false);
+        self.dirty = true;
+    }
+
+    /// Generate one selector per line of a `width` x `height` pattern, so that the
+    /// selectors don't repeat across it. A longer run of selectors from a seed starts with
+    /// the same selectors as a shorter one, so resizing only adds or removes lines at the
+    /// far edges. Selectors that were edited by hand are left alone. With a `selector_len`,
+    /// that many are generated instead, whatever the size.
+    pub fn fit_selectors(&mut self, width: u32, height: u32) {
+        let [horz_count, vert_count, diag_count] = match self.selector_len {
+            Some(len) => [len; 3],
+            None => self.line_counts(width, height),
+        }
+        .map(|count| count.max(1));
+        if diag_count != self.diag_count {
+            self.diag_count = diag_count;
+            self.regenerate_diag_selectors();
+        }
+        if (horz_count, vert_count) == (self.horz_count, self.vert_count) {
+            return;
+        }
+
+        self.horz_count = horz_count;
+        self.vert_count = vert_count;
+        if !self.edited {
+            self.regenerate_selectors();
+        }
+    }
+
+    /// How many lines of a `width` x `height` pattern take their selectors from the
+    /// horizontal, vertical and diagonal selectors
+    fn line_counts(&self, width: u32, height: u32) -> [usize; 3] {
+        match self.grid {
+            Grid::Square => [
+                line_positions(self.line_gaps(Axis::Horizontal), 0.0, height as f32, false).count(),
+                line_positions(self.line_gaps(Axis::Vertical), 0.0, width as f32, false).count(),
+                self.diag_count,
+            ],
+            Grid::Triangular => {
+                let pattern = Pattern::new(self, width, height);
+                TRIANGLE_FAMILIES.map(|family| pattern.triangle_lines(family).count())
+            }
+            Grid::Polar => {
+                let grid = Pattern::new(self, width, height).polar_grid();
+                // Ring 0 is the center, which has no stitches but keeps the numbering simple
+                [
+                    grid.rings as usize + 1,
+                    grid.spokes as usize,
+                    self.diag_count,
+                ]
+            }
+        }
+    }
+
+    /// How far the exported grid is shifted, as `(x, y)`. Tileable patterns always start
+    /// in the corner, so that the edges of each tile line up with the next one.
+    pub fn grid_offset(&self) -> (f32, f32) {
+        if self.tileable {
+            (0.0, 0.0)
+        } else {
+            (self.offset_x, self.offset_y)
+        }
+    }
+
+    /// The gaps between the lines running along `axis` on a square grid: first the gap
+    /// after each even-numbered line, then the gap after each odd-numbered one. They are
+    /// both the axis' spacing unless `spacing_alt` is set.
+    pub fn line_gaps(&self, axis: Axis) -> (f32, f32) {
+        let spacing = self.spacing(axis);
+        let alt = self.spacing_alt.map_or(spacing, |alt| self.snap(alt));
+        (spacing, alt)
+    }
+
+    /// The spacing between the lines running along `axis`, raised to `MIN_SPACING` if it
+    /// is any smaller, and rounded to whole pixels if the settings `snap_to_pixels`
+    pub fn spacing(&self, axis: Axis) -> f32 {
+        self.snap(match axis {
+            Axis::Horizontal => self.horz_spacing,
+            Axis::Vertical => self.vert_spacing,
+        })
+    }
+
+    /// The gap of `spacing` between two lines as it is drawn (see `spacing`)
+    fn snap(&self, spacing: f32) -> f32 {
+        let spacing = if self.snap_to_pixels {
+            spacing.round()
+        } else {
+            spacing
+        };
+        spacing.max(MIN_SPACING)
+    }
+
+    /// How to draw the stitches along the lines running along `axis`
+    pub fn line_style(&self, axis: Axis) -> LineStyle {
+        let color = match axis {
+            Axis::Horizontal => self.horz_color,
+            Axis::Vertical => self.vert_color,
+        };
+        let (color, color_end) = if self.gradient {
+            (self.color_start, Some(self.color_end))
+        } else {
+            (color, None)
+        };
+        LineStyle {
+            color,
+            color_end,
+            weight: self.line_weight,
+            dash_ratio: self.dash_ratio,
+            dashes_per_cell: self.dashes_per_cell,
+            antialias: self.antialias,
+            round_caps: self.round_caps,
+            opacity: self.opacity,
+        }
+    }
+
+    /// Whether every color the pattern is drawn with is a shade of gray, so that saving it
+    /// as a gray PNG (see `PngColor`) doesn't change how it looks. Blending grays, for the
+    /// anti-aliased edges or the opacity, only gives more grays.
+    pub fn is_gray(&self) -> bool {
+        if self.color_by_loop && self.grid == Grid::Square {
+            return false;
+        }
+        let mut colors = vec![self.background_color];
+        if self.gradient {
+            colors.extend([self.color_start, self.color_end]);
+        } else {
+            colors.extend([self.horz_color, self.vert_color]);
+        }
+        if self.draw_border {
+            colors.push(self.border_color);
+        }
+        if self.draw_caption {
+            colors.push(self.caption_color);
+        }
+        colors.into_iter().all(Rgb::is_gray)
+    }
+
+    /// The colors the stitches are drawn in that don't stand out from the background by at
+    /// least `MIN_CONTRAST_RATIO` once both are seen through `filter`
+    pub fn low_contrast_colors(&self, filter: ContrastFilter) -> Vec<Rgb> {
+        let colors = if self.color_by_loop && self.grid == Grid::Square {
+            LOOP_PALETTE.to_vec()
+        } else if self.gradient {
+            vec![self.color_start, self.color_end]
+        } else {
+            vec![self.horz_color, self.vert_color]
+        };
+        let background = filter.apply(self.background_color);
+        colors
+            .into_iter()
+            .filter(|&color| filter.apply(color).contrast_ratio(background) < MIN_CONTRAST_RATIO)
+            .collect()
+    }
+
+    /// Swap every color for its opposite (see `Rgb::inverted`), so a dark pattern on a
+    /// light background becomes a light pattern on a dark one
+    pub fn invert_colors(&mut self) {
+        for color in [
+            &mut self.background_color,
+            &mut self.horz_color,
+            &mut self.vert_color,
+            &mut self.color_start,
+            &mut self.color_end,
+            &mut self.border_color,
+            &mut self.caption_color,
+        ] {
+            *color = color.inverted();
+        }
+    }
+
+    /// The selectors for the lines running along `axis`
+    pub fn selectors(&self, axis: Axis) -> &[bool] {
+        match axis {
+            Axis::Horizontal => &self.horz_selectors,
+            Axis::Vertical => &self.vert_selectors,
+        }
+    }
+
+    /// Replace the selectors on `axis` by hand. If the vertical selectors are derived from
+    /// the horizontal ones, they follow the new horizontal selectors.
+    pub fn set_selectors(&mut self, axis: Axis, selectors: Vec<bool>) {
+        match axis {
+            Axis::Horizontal => self.horz_selectors = selectors,
+            Axis::Vertical => self.vert_selectors = selectors,
+        }
+        if axis == Axis::Horizontal && self.vert_derivation != VertDerivation::Seed {
+            self.vert_selectors = self.vert_derivation.derive(&self.horz_selectors);
+        }
+
+        self.edited = true;
+        self.dirty = true;
+    }
+
+    /// Flip the selector at `index` on `axis` by hand, returning its new value. If the
+    /// vertical selectors are derived from the horizontal ones, they follow the edit.
+    ///
+    /// Panics if `index` is out of range for that axis' selectors.
+    pub fn toggle_selector(&mut self, axis: Axis, index: usize) -> bool {
+        let selectors = match axis {
+            Axis::Horizontal => &mut self.horz_selectors,
+            Axis::Vertical => &mut self.vert_selectors,
+        };
+        selectors[index] = !selectors[index];
+        let value = selectors[index];
+
+        if axis == Axis::Horizontal && self.vert_derivation != VertDerivation::Seed {
+            self.vert_selectors = self.vert_derivation.derive(&self.horz_selectors);
+        }
+
+        self.edited = true;
+        self.dirty = true;
+        value
+    }
+
+    /// The size of the pattern to export for a window of `width` x `height`, after applying
+    /// the `grid_lock` setting. Tileable patterns are always locked to a whole number of
+    /// dash periods (a dash and a gap, two cells) so that the dashes wrap around the edges.
+    /// With `spacing_alt` set, the cells alternate in size, so the lock is always to whole
+    /// pairs of them.
+    pub fn pattern_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let step = |axis| {
+            let (even, odd) = self.line_gaps(axis);
+            match self.spacing_alt {
+                Some(_) => even + odd,
+                None if self.tileable => 2.0 * even,
+                None => even,
+            }
+        };
+        let lock = match self.grid_lock {
+            GridLock::Off if self.tileable => GridLock::Down,
+            lock => lock,
+        };
+        (
+            lock.apply(width, step(Axis::Vertical)),
+            lock.apply(height, step(Axis::Horizontal)),
+        )
+    }
+
+    /// The size of the image to export for a window of `width` x `height`: the pattern,
+    /// plus the legend strip below it if there is one
+    pub fn export_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width, height) = self.pattern_size(width, height);
+        (width, height + self.legend_height())
+    }
+
+    /// The height of the legend strip added below exported patterns
+    pub fn legend_height(&self) -> u32 {
+        if self.draw_legend {
+            LEGEND_HEIGHT
+        } else {
+            0
+        }
+    }
+
+    /// The file name given by `file_name_template`, with each placeholder replaced by its
+    /// setting. Characters that aren't allowed in file names are replaced with `_`.
+    pub fn file_name(&self) -> String {
+        let name = self
+            .file_name_template
+            .replace("{horz_seed}", &self.horz_seed.to_string())
+            .replace("{vert_seed}", &self.vert_seed.to_string())
+            .replace("{spacing}", &self.spacing_label());
+        name.chars()
+            .map(|c| {
+                if c.is_control()
+                    || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|')
+                {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect()
+    }
+
+    /// A short description of the spacing: a single number if both axes have the same
+    /// spacing, otherwise `<horizontal>x<vertical>`
+    pub fn spacing_label(&self) -> String {
+        if self.horz_spacing == self.vert_spacing {
+            self.horz_spacing.to_string()
+        } else {
+            format!("{}x{}", self.horz_spacing, self.vert_spacing)
+        }
+    }
+
+    /// The text of the caption to write into exported images
+    pub fn caption(&self) -> String {
+        if self.caption_text.is_empty() {
+            format!(
+                "h{} v{} s{}",
+                self.horz_seed,
+                self.vert_seed,
+                self.spacing_label()
+            )
+        } else {
+            self.caption_text.clone()
+        }
+    }
+}
+
+/// How a line of stitches is drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineStyle {
+    pub color: Rgb,
+    /// If set, the color fades from `color` to this along the line (see `gradient_position`)
+    pub color_end: Option<Rgb>,
+    pub weight: f32,
+    /// The fraction of each cell that is stitched (see `RectSettings::dash_ratio`)
+    pub dash_ratio: f32,
+    /// How many dashes each cell is split into (see `RectSettings::dashes_per_cell`)
+    pub dashes_per_cell: u32,
+    /// Whether to blend the edges into exported images (see `RectSettings::antialias`)
+    pub antialias: bool,
+    /// Whether to round off the ends of the stitches (see `RectSettings::round_caps`)
+    pub round_caps: bool,
+    /// How much of the color covers what is under it (see `RectSettings::opacity`)
+    pub opacity: f32,
+}
+
+impl LineStyle {
+    /// The color `t` of the way along a line, from 0.0 at its start to 1.0 at its end
+    pub fn color_at(&self, t: f32) -> Rgb {
+        match self.color_end {
+            Some(end) => self.color.lerp(end, t.clamp(0.0, 1.0)),
+            None => self.color,
+        }
+    }
+}
+
+/// How far `point` is along a line running along `axis` across a `width` x `height`
+/// image, from 0.0 at the image's left (or top) edge to 1.0 at its right (or bottom) edge
+pub fn gradient_position(axis: Axis, [x, y]: [f32; 2], width: f32, height: f32) -> f32 {
+    match axis {
+        Axis::Horizontal => x / width,
+        Axis::Vertical => y / height,
+    }
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        LineStyle {
+            color: Rgb::BLACK,
+            color_end: None,
+            weight: 3.0,
+            dash_ratio: 1.0,
+            dashes_per_cell: 1,
+            antialias: false,
+            round_caps: false,
+            opacity: 1.0,
+        }
+    }
+}
+
+/// How the vertical selectors are produced. Deriving them from the horizontal selectors
+/// gives the pattern a diagonal symmetry.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum VertDerivation {
+    /// Generate them independently from `vert_seed`
+    Seed,
+    /// Use the horizontal selectors in reverse order
+    Reverse,
+    /// Use the horizontal selectors rotated left by this many places
+    Rotate(usize),
+}
+
+impl VertDerivation {
+    pub fn label(&self) -> &'static str {
+        match self {
+            VertDerivation::Seed => "From vertical seed",
+            VertDerivation::Reverse => "Reverse of horizontal",
+            VertDerivation::Rotate(_) => "Rotation of horizontal",
+        }
+    }
+
+    /// Derive vertical selectors from `horz_selectors`. For `Seed` there is nothing to
+    /// derive from, so the horizontal selectors are returned unchanged.
+    pub fn derive(&self, horz_selectors: &[bool]) -> Vec<bool> {
+        let mut selectors = horz_selectors.to_vec();
+        match self {
+            VertDerivation::Seed => {}
+            VertDerivation::Reverse => selectors.reverse(),
+            VertDerivation::Rotate(k) => {
+                if !selectors.is_empty() {
+                    let k = k % selectors.len();
+                    selectors.rotate_left(k);
+                }
+            }
+        }
+        selectors
+    }
+}
+
+/// How to adjust export dimensions so that they hold a whole number of grid cells
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GridLock {
+    /// Export at exactly the requested size, which may leave partial cells at the edges
+    Off,
+    /// Shrink to the nearest whole multiple of the spacing
+    Down,
+    /// Grow to the nearest whole multiple of the spacing
+    Up,
+}
+
+impl GridLock {
+    pub const ALL: [GridLock; 3] = [GridLock::Off, GridLock::Down, GridLock::Up];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GridLock::Off => "Off",
+            GridLock::Down => "Round down",
+            GridLock::Up => "Round up",
+        }
+    }
+
+    /// Adjust `dimension` (in pixels) to a whole multiple of `spacing`. At least one cell
+    /// is always kept.
+    pub fn apply(&self, dimension: u32, spacing: f32) -> u32 {
+        let cells = dimension as f32 / spacing;
+        let cells = match self {
+            GridLock::Off => return dimension,
+            GridLock::Down => cells.floor(),
+            GridLock::Up => cells.ceil(),
+        };
+        (cells.max(1.0) * spacing).round() as u32
+    }
+}
+
+/// The width to height ratio to letterbox exported PNGs to. The image is padded with the
+/// background color on two sides, so the pattern keeps its own shape in the middle of it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AspectRatio {
+    /// Keep the shape of the export size, which follows the window unless a custom size
+    /// is set
+    Window,
+    Square,
+    FourThree,
+    SixteenNine,
+}
+
+impl AspectRatio {
+    pub const ALL: [AspectRatio; 4] = [
+        AspectRatio::Window,
+        AspectRatio::Square,
+        AspectRatio::FourThree,
+        AspectRatio::SixteenNine,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AspectRatio::Window => "Match window",
+            AspectRatio::Square => "1:1",
+            AspectRatio::FourThree => "4:3",
+            AspectRatio::SixteenNine => "16:9",
+        }
+    }
+
+    /// The width and height that the ratio is between, if it isn't `Window`
+    fn ratio(&self) -> Option<(u32, u32)> {
+        match self {
+            AspectRatio::Window => None,
+            AspectRatio::Square => Some((1, 1)),
+            AspectRatio::FourThree => Some((4, 3)),
+            AspectRatio::SixteenNine => Some((16, 9)),
+        }
+    }
+
+    /// The size of a `width` x `height` image once it is padded out to this ratio. Only
+    /// one side grows, and neither shrinks.
+    pub fn letterbox(&self, width: u32, height: u32) -> (u32, u32) {
+        let Some((ratio_width, ratio_height)) = self.ratio() else {
+            return (width, height);
+        };
+        // Compare `width / height` with the ratio without dividing
+        let (wide, tall) = (
+            width as u64 * ratio_height as u64,
+            height as u64 * ratio_width as u64,
+        );
+        if wide < tall {
+            (tall.div_ceil(ratio_height as u64) as u32, height)
+        } else {
+            (width, wide.div_ceil(ratio_width as u64) as u32)
+        }
+    }
+}
+
+/// Pad the `width` x `height` RGBA `buffer` with the `background` color out to
+/// `aspect_ratio` (see `AspectRatio::letterbox`), keeping the image in the middle. Returns
+/// the padded buffer and its size.
+pub fn letterbox_rgba(
+    buffer: Vec<u8>,
+    width: u32,
+    height: u32,
+    aspect_ratio: AspectRatio,
+    background: Rgb,
+) -> (Vec<u8>, u32, u32) {
+    let (padded_width, padded_height) = aspect_ratio.letterbox(width, height);
+    if (padded_width, padded_height) == (width, height) {
+        return (buffer, width, height);
+    }
+
+    let mut padded = background_buffer(padded_width, padded_height, background);
+    let left = (padded_width - width) as usize / 2;
+    let top = (padded_height - height) as usize / 2;
+    let row_len = width as usize * 4;
+    for (y, row) in buffer.chunks_exact(row_len).enumerate() {
+        let start = ((top + y) * padded_width as usize + left) * 4;
+        padded[start..start + row_len].copy_from_slice(row);
+    }
+    (padded, padded_width, padded_height)
+}
+
+/// A way to show the preview of a pattern to check how it reads for low-vision viewers.
+/// Only the preview is filtered, never the exports.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContrastFilter {
+    /// The colors as they are
+    Off,
+    /// Every color as the gray of how bright it looks (see `Rgb::luma`)
+    Grayscale,
+    /// Every color halfway to mid gray, so they all stand out half as much from each other
+    Reduced,
+}
+
+impl ContrastFilter {
+    pub const ALL: [ContrastFilter; 3] = [
+        ContrastFilter::Off,
+        ContrastFilter::Grayscale,
+        ContrastFilter::Reduced,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ContrastFilter::Off => "Normal",
+            ContrastFilter::Grayscale => "Grayscale",
+            ContrastFilter::Reduced => "Reduced contrast",
+        }
+    }
+
+    /// `color` as it looks through the filter
+    pub fn apply(self, color: Rgb) -> Rgb {
+        match self {
+            ContrastFilter::Off => color,
+            ContrastFilter::Grayscale => {
+                let gray = color.luma().round() as u8;
+                Rgb::new(gray, gray, gray)
+            }
+            ContrastFilter::Reduced => color.lerp(Rgb::new(128, 128, 128), 0.5),
+        }
+    }
+
+    /// Pass every pixel of the RGBA `buffer` through the filter, leaving the alpha as it is
+    pub fn apply_rgba(self, buffer: &mut [u8]) {
+        if self == ContrastFilter::Off {
+            return;
+        }
+        for pixel in buffer.chunks_exact_mut(4) {
+            let color = self.apply(Rgb::new(pixel[0], pixel[1], pixel[2]));
+            pixel[..3].copy_from_slice(&<[u8; 3]>::from(color));
+        }
+    }
+}
+
+/// The kind of pixels an exported PNG is saved with. A black and white pattern takes a
+/// quarter of the bytes as 8-bit gray and a thirty-second as 1-bit, before compression.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PngColor {
+    /// Full color, with an alpha channel
+    Rgba,
+    /// 8 bits of gray per pixel. Colors are saved as how bright they look.
+    Gray,
+    /// 1 bit per pixel. Each pixel is saved as black if it looks darker than mid gray, and
+    /// as white otherwise, so colors and anti-aliased edges are lost.
+    OneBit,
+}
+
+impl PngColor {
+    pub const ALL: [PngColor; 3] = [PngColor::Rgba, PngColor::Gray, PngColor::OneBit];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PngColor::Rgba => "Color",
+            PngColor::Gray => "8-bit gray",
+            PngColor::OneBit => "1-bit black and white",
+        }
+    }
+
+    fn color_type(&self) -> (png::ColorType, png::BitDepth) {
+        match self {
+            PngColor::Rgba => (png::ColorType::Rgba, png::BitDepth::Eight),
+            PngColor::Gray => (png::ColorType::Grayscale, png::BitDepth::Eight),
+            PngColor::OneBit => (png::ColorType::Grayscale, png::BitDepth::One),
+        }
+    }
+
+    /// The rows of the `width` pixel wide RGBA `buffer` as this kind of pixels. 1-bit rows
+    /// are packed 8 pixels to a byte, from the high bit down, and padded to a whole byte.
+    fn pack(&self, buffer: Vec<u8>, width: u32) -> Vec<u8> {
+        let luma = |pixel: &[u8]| Rgb::new(pixel[0], pixel[1], pixel[2]).luma();
+        match self {
+            PngColor::Rgba => buffer,
+            PngColor::Gray => buffer
+                .chunks_exact(4)
+                .map(|pixel| luma(pixel).round() as u8)
+                .collect(),
+            PngColor::OneBit => buffer
+                .chunks_exact(width as usize * 4)
+                .flat_map(|row| {
+                    row.chunks(8 * 4).map(|pixels| {
+                        pixels
+                            .chunks_exact(4)
+                            .enumerate()
+                            .fold(0, |byte, (i, pixel)| {
+                                if luma(pixel) >= 128.0 {
+                                    byte | 0x80 >> i
+                                } else {
+                                    byte
+                                }
+                            })
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The shape of the grid that the stitches run along
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Grid {
+    /// Horizontal and vertical lines, meeting at the corners of squares
+    Square,
+    /// Horizontal lines, and lines running down to the right and down to the left at 60° to
+    /// them, meeting at the corners of equilateral triangles. The horizontal spacing is the
+    /// side of the triangles.
+    Triangular,
+    /// Rings around the center of the image, crossed by spokes running out from it. The
+    /// horizontal selectors and spacing are for the rings, and the vertical ones for the
+    /// spokes (see `PolarGrid`).
+    Polar,
+}
+
+impl Grid {
+    pub const ALL: [Grid; 3] = [Grid::Square, Grid::Triangular, Grid::Polar];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Grid::Square => "Square",
+            Grid::Triangular => "Triangular",
+            Grid::Polar => "Polar",
+        }
+    }
+}
+
+/// A corner of the canvas
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    pub const ALL: [Corner; 4] = [
+        Corner::TopLeft,
+        Corner::TopRight,
+        Corner::BottomLeft,
+        Corner::BottomRight,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Corner::TopLeft => "Top left",
+            Corner::TopRight => "Top right",
+            Corner::BottomLeft => "Bottom left",
+            Corner::BottomRight => "Bottom right",
+        }
+    }
+}
+
+/// Fill `selectors` with the random bools generated from `seed`, each of which is true
+/// with a chance of `bias`. Biases outside of 0 to 1 are clamped, and the rest are rounded
+/// to the nearest step (see `quantize_bias`), so biases that differ by less than a step
+/// give the same selectors.
+///
+/// The bools are drawn one at a time from the seed's stream, so the first selectors are the
+/// same however many are filled. Changing `selector_len` or the window size only adds or
+/// removes selectors at the end, unless they are mirrored (see `mirror_selectors`).
+///
+/// The bools come from a `Pcg64` seeded through `rand_seeder`, rather than from an RNG like
+/// `StdRng` whose algorithm may change, so a seed always gives the same pattern. Seeding,
+/// the PCG stream, and rand's bool sampling only change between minor versions of those
+/// crates, so bumping any of them in `Cargo.toml` can change every saved pattern.
+pub fn fill_selectors(selectors: &mut [bool], seed: u64, bias: f64) {
+    // Seeds used to be u8s, which hash differently, so keep hashing the small seeds as
+    // u8s to give them the same patterns as before
+    let mut rng: Pcg64 = match u8::try_from(seed) {
+        Ok(seed) => Seeder::from(seed).make_rng(),
+        Err(_) => Seeder::from(seed).make_rng(),
+    };
+    let bias = quantize_bias(bias);
+    if bias == 0.5 || bias.is_nan() {
+        // Fair coin flips, exactly as generated before there was a bias, so that every
+        // seed keeps its pattern
+        rng.fill(selectors);
+    } else {
+        let bias = bias.clamp(0.0, 1.0);
+        for selector in selectors {
+            *selector = rng.gen_bool(bias);
+        }
+    }
+}
+
+/// `bias`, clamped to 0 to 1 and rounded to the nearest 1/`BIAS_STEPS`
+pub fn quantize_bias(bias: f64) -> f64 {
+    (bias.clamp(0.0, 1.0) * BIAS_STEPS as f64).round() / BIAS_STEPS as f64
+}
+
+/// Saves a bias as the whole number of `BIAS_STEPS` it is rounded to (see `quantize_bias`)
+mod bias_steps {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{quantize_bias, BIAS_STEPS};
+
+    pub fn serialize<S: Serializer>(bias: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32((quantize_bias(*bias) * BIAS_STEPS as f64) as u32)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        Ok(u32::deserialize(deserializer)? as f64 / BIAS_STEPS as f64)
+    }
+}
+
+/// Whether the selectors of `settings` are exactly the ones its seeds (or texts) and biases
+/// generate, as many as it has. If not, they were edited by hand, either in the app or in
+/// the file they were loaded from.
+pub fn selectors_match_seed(settings: &RectSettings) -> bool {
+    let mut generated = settings.clone();
+    generated.horz_count = settings.horz_selectors.len();
+    generated.vert_count = settings.vert_selectors.len();
+    generated.regenerate_selectors();
+    generated.horz_selectors == settings.horz_selectors
+        && generated.vert_selectors == settings.vert_selectors
+}
+
+/// One selector per byte of `text`, true for the bytes with their lowest bit set. In
+/// ASCII that is every other letter, so each word makes its own pattern.
+pub fn selectors_from_text(text: &str) -> Vec<bool> {
+    text.bytes().map(|byte| byte & 1 == 1).collect()
+}
+
+/// The selectors as a string of bits, `1` for true and `0` for false
+pub fn selectors_to_bits(selectors: &[bool]) -> String {
+    selectors
+        .iter()
+        .map(|&selector| if selector { '1' } else { '0' })
+        .collect()
+}
+
+/// The selectors written as a string of bits by `selectors_to_bits`. Whitespace between
+/// the bits is ignored.
+pub fn selectors_from_bits(bits: &str) -> Result<Vec<bool>, BitsError> {
+    let selectors = bits
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            _ => Err(BitsError::NotBinary(c)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if selectors.is_empty() {
+        return Err(BitsError::Empty);
+    }
+    Ok(selectors)
+}
+
+/// Why a string of bits couldn't be read as selectors (see `selectors_from_bits`)
+#[derive(Debug)]
+pub enum BitsError {
+    /// There are no bits at all
+    Empty,
+    /// The string has a character other than `0`, `1` or whitespace
+    NotBinary(char),
+}
+
+impl fmt::Display for BitsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BitsError::Empty => write!(f, "Enter at least one bit"),
+            BitsError::NotBinary(c) => write!(f, "'{c}' isn't a bit, only 0 and 1 are"),
+        }
+    }
+}
+
+impl Error for BitsError {}
+
+/// The selectors for one axis: from `text` if there is any, otherwise `count` selectors
+/// generated from `seed` with `bias`. With `mirror`, the second half is the first half
+/// reflected (see `mirror_selectors`).
+fn generate_selectors(text: &str, seed: u64, bias: f64, count: usize, mirror: bool) -> Vec<bool> {
+    let mut selectors = if text.is_empty() {
+        let mut selectors = vec![false; count];
+        fill_selectors(&mut selectors, seed, bias);
+        selectors
+    } else {
+        selectors_from_text(text)
+    };
+    if mirror {
+        mirror_selectors(&mut selectors);
+    }
+    selectors
+}
+
+/// Reflect the first half of `selectors` onto the second half, so they read the same
+/// backwards. The middle selector of an odd number of them is kept as it is.
+fn mirror_selectors(selectors: &mut [bool]) {
+    let len = selectors.len();
+    for idx in 0..len / 2 {
+        selectors[len - 1 - idx] = selectors[idx];
+    }
+}
+
+/// The fraction of `selectors` that are true, or 0 if there are none
+fn selector_density(selectors: &[bool]) -> f32 {
+    if selectors.is_empty() {
+        return 0.0;
+    }
+    selectors.iter().filter(|&&s| s).count() as f32 / selectors.len() as f32
+}
+
+/// The most random seeds `seed_in_density_band` will try before giving up
+const MAX_DENSITY_TRIES: usize = 100;
+
+/// Pick a random seed whose `len` selectors, generated with `bias`, have a density
+/// (fraction of true) within `min..=max`. Up to `MAX_DENSITY_TRIES` seeds are tried; if
+/// none of them fit, the one with the density closest to the band is returned, so this
+/// always terminates.
+pub fn seed_in_density_band(rng: &mut impl Rng, len: usize, bias: f64, min: f32, max: f32) -> u64 {
+    let mut selectors = vec![false; len];
+    let mut best = (f32::INFINITY, 0);
+
+    for _ in 0..MAX_DENSITY_TRIES {
+        let seed = rng.gen();
+        fill_selectors(&mut selectors, seed, bias);
+
+        // How far the density is outside of the band, 0 if it is inside
+        let density = selector_density(&selectors);
+        let distance = (min - density).max(density - max).max(0.0);
+        if distance == 0.0 {
+            return seed;
+        }
+        if distance < best.0 {
+            best = (distance, seed);
+        }
+    }
+
+    best.1
+}
+
+/// The selector for line number `line_idx`. The selectors repeat past the last one, and
+/// wrap around backwards for lines before line 0. Having no selectors at all is the same
+/// as having only false ones.
+pub fn selector_at(selectors: &[bool], line_idx: i64) -> bool {
+    if selectors.is_empty() {
+        return false;
+    }
+    selectors[line_idx.rem_euclid(selectors.len() as i64) as usize]
+}
+
+/// Whether horizontal line number `line_idx` starts with a dash. In `brick` mode the odd
+/// lines are shifted along by half a period (one dash), which is the same as flipping
+/// whether they start with a dash, so the stitches stagger like courses of bricks.
+pub fn brick_starts_on(on_off_selectors: &[bool], line_idx: i64, brick: bool) -> bool {
+    selector_at(on_off_selectors, line_idx) ^ (brick && line_idx.rem_euclid(2) == 1)
+}
+
+/// Draw the pattern as text, `cols` cells wide and `rows` cells tall, with `_` for the
+/// stitches along the horizontal lines and `|` for the stitches along the vertical lines.
+/// Each cell takes two characters: its left edge, then its bottom edge. The first line
+/// holds the top edges of the first row of cells. Only the selectors (and `brick`) are
+/// used, so the spacing, offsets, and grid shape are ignored.
+pub fn pattern_to_ascii(settings: &RectSettings, cols: usize, rows: usize) -> String {
+    let horz_stitch = |line, cell| selector_stitch(settings, Axis::Horizontal, line, cell);
+    let vert_stitch = |line, cell| selector_stitch(settings, Axis::Vertical, line, cell);
+
+    let mut ascii = String::new();
+    for col in 0..cols {
+        ascii.push(' ');
+        ascii.push(if horz_stitch(0, col) { '_' } else { ' ' });
+    }
+    ascii.push('\n');
+    for row in 0..rows {
+        for col in 0..=cols {
+            ascii.push(if vert_stitch(col, row) { '|' } else { ' ' });
+            if col < cols {
+                ascii.push(if horz_stitch(row + 1, col) { '_' } else { ' ' });
+            }
+        }
+        ascii.push('\n');
+    }
+    ascii
+}
+
+/// Write the pattern as a stitch chart in CSV, with one row of `cols` values for each of
+/// `rows` rows of cells. Each value is for the top left corner of its cell, where a
+/// horizontal and a vertical line cross, and says which stitches leave it:
+///
+/// - `H` for a stitch to the right, along the top edge of the cell
+/// - `V` for a stitch downwards, along the left edge of the cell
+/// - `+` for both
+/// - nothing for neither
+///
+/// Like `pattern_to_ascii`, only the selectors (and `brick`) are used.
+pub fn pattern_to_csv(settings: &RectSettings, cols: usize, rows: usize) -> String {
+    let mut csv = String::new();
+    for row in 0..rows {
+        let values: Vec<_> = (0..cols)
+            .map(|col| {
+                let horz = selector_stitch(settings, Axis::Horizontal, row, col);
+                let vert = selector_stitch(settings, Axis::Vertical, col, row);
+                match (horz, vert) {
+                    (true, true) => "+",
+                    (true, false) => "H",
+                    (false, true) => "V",
+                    (false, false) => "",
+                }
+            })
+            .collect();
+        csv.push_str(&values.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Write the stitch chart of the pattern (see `pattern_to_csv`) to `path`
+pub fn write_csv(
+    path: &Path,
+    settings: &RectSettings,
+    cols: usize,
+    rows: usize,
+) -> Result<(), Box<dyn Error>> {
+    fs::write(path, pattern_to_csv(settings, cols, rows))?;
+    Ok(())
+}
+
+/// How many separate pieces the stitches of `cols` x `rows` cells of the pattern form,
+/// where stitches that meet at a grid point are joined. Every point inside the pattern is
+/// where exactly two stitches meet, so each piece is either a closed loop or, if it reaches
+/// the edge, a path that would close up further out. Like `pattern_to_ascii`, only the
+/// selectors (and `brick`) are used.
+pub fn count_loops(settings: &RectSettings, cols: usize, rows: usize) -> usize {
+    stitch_loops(settings, cols, rows).1
+}
+
+/// Which piece (see `count_loops`) the stitches at each grid point of `cols` x `rows` cells
+/// of the pattern belong to, numbered from 0 in the order their first points come up, row
+/// by row. The points are in rows of `cols + 1`, and those without any stitches have no
+/// number. Also returns how many pieces there are.
+fn stitch_loops(settings: &RectSettings, cols: usize, rows: usize) -> (Vec<Option<usize>>, usize) {
+    let point = |col: usize, row: usize| row * (cols + 1) + col;
+    let mut pieces = Pieces::new((cols + 1) * (rows + 1));
+    let mut stitched = vec![false; pieces.len()];
+    let mut join = |a: usize, b: usize| {
+        stitched[a] = true;
+        stitched[b] = true;
+        pieces.join(a, b);
+    };
+    for row in 0..=rows {
+        for col in 0..cols {
+            if selector_stitch(settings, Axis::Horizontal, row, col) {
+                join(point(col, row), point(col + 1, row));
+            }
+        }
+    }
+    for col in 0..=cols {
+        for row in 0..rows {
+            if selector_stitch(settings, Axis::Vertical, col, row) {
+                join(point(col, row), point(col, row + 1));
+            }
+        }
+    }
+
+    let mut numbers = vec![None; pieces.len()];
+    let mut count = 0;
+    for point in (0..pieces.len()).filter(|&point| stitched[point]) {
+        let root = pieces.root(point);
+        let number = *numbers[root].get_or_insert_with(|| {
+            count += 1;
+            count - 1
+        });
+        numbers[point] = Some(number);
+    }
+    (numbers, count)
+}
+
+/// Points joined into pieces, where each piece is a tree of points pointing at the one
+/// they were joined to, and the point at the root stands for the whole piece
+struct Pieces {
+    parents: Vec<usize>,
+}
+
+impl Pieces {
+    /// `len` points, each a piece of its own
+    fn new(len: usize) -> Self {
+        Pieces {
+            parents: (0..len).collect(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    /// The point that stands for the piece `point` is in. The lowest point of a piece is
+    /// always its root.
+    fn root(&mut self, mut point: usize) -> usize {
+        while self.parents[point] != point {
+            self.parents[point] = self.parents[self.parents[point]];
+            point = self.parents[point];
+        }
+        point
+    }
+
+    /// Join the pieces that points `a` and `b` are in
+    fn join(&mut self, a: usize, b: usize) {
+        let (root_a, root_b) = (self.root(a), self.root(b));
+        self.parents[root_a.max(root_b)] = root_a.min(root_b);
+    }
+}
+
+/// Whether cell number `cell` along line number `line` of `axis` is stitched, going only by
+/// the selectors (and `brick`). Like `dash_spans`, the cells along a line alternate
+/// between stitches and gaps.
+fn selector_stitch(settings: &RectSettings, axis: Axis, line: usize, cell: usize) -> bool {
+    let starts_on = match axis {
+        Axis::Horizontal => brick_starts_on(&settings.horz_selectors, line as i64, settings.brick),
+        Axis::Vertical => selector_at(&settings.vert_selectors, line as i64),
+    };
+    starts_on == cell.is_multiple_of(2)
+}
+
+/// Render the pattern as a `width` x `height` PNG and write it to `path`, with the
+/// settings JSON in a `Settings` tEXt chunk so it can be loaded again, or a compressed zTXt
+/// chunk if it is longer than `COMPRESSED_SETTINGS_LEN`. The pHYs chunk gives the physical
+/// size at the settings' `dpi`. The image is then padded out to the settings'
+/// `aspect_ratio`, so it can be bigger than `width` x `height`.
+pub fn write_png(
+    path: &Path,
+    settings: &RectSettings,
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn Error>> {
+    write_png_to(BufWriter::new(File::create(path)?), settings, width, height)
+}
+
+/// Like `write_png`, but writes the PNG to `writer` rather than a file
+pub fn write_png_to(
+    writer: impl Write,
+    settings: &RectSettings,
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn Error>> {
+    write_layers_png_to(writer, std::slice::from_ref(settings), width, height)
+}
+
+/// Like `write_png`, but composites each of the `layers` over the ones before it (see
+/// `render_layers_rgba`). The first layer goes in the `Settings` chunk, so apps that only
+/// know about one layer can still load it, and if there are more, all of them go in a
+/// `Layers` chunk as a JSON array. Like `load_layers_from_png`, there has to be at least one
+/// layer, otherwise nothing is written and the error is `LoadError::BadField("layers")`.
+pub fn write_layers_png(
+    path: &Path,
+    layers: &[RectSettings],
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn Error>> {
+    // Check before creating the file, so that no empty file is left behind
+    if layers.is_empty() {
+        return Err(LoadError::BadField("layers").into());
+    }
+    write_layers_png_to(BufWriter::new(File::create(path)?), layers, width, height)
+}
+
+/// Like `write_layers_png`, but writes the PNG to `writer` rather than a file
+pub fn write_layers_png_to(
+    writer: impl Write,
+    layers: &[RectSettings],
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn Error>> {
+    let Some(settings) = layers.first() else {
+        return Err(LoadError::BadField("layers").into());
+    };
+    let buffer = render_layers_rgba(width, height, layers);
+    let (buffer, width, height) = letterbox_rgba(
+        buffer,
+        width,
+        height,
+        settings.aspect_ratio,
+        settings.background_color,
+    );
+
+    let buffer = settings.png_color.pack(buffer, width);
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    let (color_type, bit_depth) = settings.png_color.color_type();
+    encoder.set_color(color_type);
+    encoder.set_depth(bit_depth);
+    // pHYs is in pixels per meter, and there are 0.0254 meters in an inch
+    let pixels_per_meter = (settings.dpi as f64 / 0.0254).round() as u32;
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: pixels_per_meter,
+        yppu: pixels_per_meter,
+        unit: png::Unit::Meter,
+    }));
+    add_json_chunk(&mut encoder, "Settings", settings.to_json())?;
+    if layers.len() > 1 {
+        let json = serde_json::to_string(layers).expect("The settings can always be serialized");
+        add_json_chunk(&mut encoder, "Layers", json)?;
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&buffer)?;
+
+    Ok(())
+}
+
+/// Add `json` to the PNG as a text chunk named `keyword`, compressed if it is longer than
+/// `COMPRESSED_SETTINGS_LEN`
+fn add_json_chunk<W: Write>(
+    encoder: &mut png::Encoder<W>,
+    keyword: &str,
+    json: String,
+) -> Result<(), png::EncodingError> {
+    if json.len() > COMPRESSED_SETTINGS_LEN {
+        encoder.add_ztxt_chunk(keyword.to_string(), json)
+    } else {
+        encoder.add_text_chunk(keyword.to_string(), json)
+    }
+}
+
+/// Write an animated `width` x `height` GIF to `path` that steps the seed for `axis`
+/// through 0 to 255, one frame per seed at `fps` frames per second. Everything else
+/// comes from `settings`, so sweeping the vertical seed only changes the pattern when
+/// the vertical selectors come from their seed.
+pub fn write_seed_sweep_gif(
+    path: &Path,
+    settings: &RectSettings,
+    width: u32,
+    height: u32,
+    axis: Axis,
+    fps: u16,
+) -> Result<(), Box<dyn Error>> {
+    let (Ok(gif_width), Ok(gif_height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(format!("{width}x{height} is too big for a GIF").into());
+    };
+    // GIF frame delays are in hundredths of a second
+    let delay = (100 / fps.max(1)).max(1);
+
+    let writer = BufWriter::new(File::create(path)?);
+    let mut encoder = gif::Encoder::new(writer, gif_width, gif_height, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    let mut frame_settings = settings.clone();
+    for seed in 0..=255 {
+        match axis {
+            Axis::Horizontal => frame_settings.horz_seed = seed,
+            Axis::Vertical => frame_settings.vert_seed = seed,
+        }
+        frame_settings.regenerate_selectors();
+
+        let mut buffer = render_rgba(width, height, &frame_settings);
+        // The fastest quantizer is plenty, since the pattern only has a few colors
+        let mut frame = gif::Frame::from_rgba_speed(gif_width, gif_height, &mut buffer, 30);
+        frame.delay = delay;
+        encoder.write_frame(&frame)?;
+    }
+
+    Ok(())
+}
+
+/// Write a PNG contact sheet to `path`: a `count` x `count` grid of `tile_size` pixel
+/// square tiles, one per pair of seeds. Going down the rows steps the horizontal seed up
+/// from `settings.horz_seed`, and going across the columns steps the vertical seed up from
+/// `settings.vert_seed`. Everything else comes from `settings`, and the tiles are split by
+/// thin gray lines.
+pub fn write_contact_sheet(
+    path: &Path,
+    settings: &RectSettings,
+    count: u32,
+    tile_size: u32,
+) -> Result<(), Box<dyn Error>> {
+    let size = count * tile_size + count.saturating_sub(1) * SHEET_SEPARATOR;
+    let buffer = render_contact_sheet(settings, count, tile_size);
+
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), size, size);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&buffer)?;
+
+    Ok(())
+}
+
+/// Render the contact sheet that `write_contact_sheet` saves into a freshly allocated RGBA
+/// buffer
+fn render_contact_sheet(settings: &RectSettings, count: u32, tile_size: u32) -> Vec<u8> {
+    let step = (tile_size + SHEET_SEPARATOR) as usize;
+    let size = (count * tile_size + count.saturating_sub(1) * SHEET_SEPARATOR) as usize;
+    let [red, green, blue] = SHEET_SEPARATOR_COLOR.into();
+    let mut sheet = [red, green, blue, 255].repeat(size * size);
+
+    let tiles: Vec<Vec<u8>> = (0..count * count)
+        .into_par_iter()
+        .map(|tile| {
+            let mut tile_settings = settings.clone();
+            tile_settings.horz_seed = settings.horz_seed.wrapping_add((tile / count) as u64);
+            tile_settings.vert_seed = settings.vert_seed.wrapping_add((tile % count) as u64);
+            tile_settings.fit_selectors(tile_size, tile_size);
+            tile_settings.regenerate_selectors();
+            pattern_pixels(tile_size, tile_size, &tile_settings)
+        })
+        .collect();
+
+    // Copy each tile in row by row, since a row of the tile is a run of a row of the sheet
+    let row_len = tile_size as usize * 4;
+    for (tile, pixels) in tiles.iter().enumerate() {
+        let (row, col) = (tile / count as usize, tile % count as usize);
+        for (y, tile_row) in pixels.chunks_exact(row_len).enumerate() {
+            let start = ((row * step + y) * size + col * step) * 4;
+            sheet[start..start + row_len].copy_from_slice(tile_row);
+        }
+    }
+    sheet
+}
+
+/// Render the pattern as a `width` x `height` JPEG at `quality` (1 to 100) and write it
+/// to `path`. JPEG has no text chunks, so the settings JSON goes in the EXIF
+/// `UserComment` instead.
+pub fn write_jpeg(
+    path: &Path,
+    settings: &RectSettings,
+    width: u32,
+    height: u32,
+    quality: u8,
+) -> Result<(), Box<dyn Error>> {
+    // JPEG has no alpha channel, and the rendered image is opaque anyway
+    let rgb: Vec<u8> = render_rgba(width, height, settings)
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, quality.clamp(1, 100)).encode(
+        &rgb,
+        width,
+        height,
+        image::ColorType::Rgb8,
+    )?;
+
+    // The EXIF segment goes straight after the start of image marker
+    let (start, rest) = jpeg.split_at(2);
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(start)?;
+    writer.write_all(&exif_user_comment(&settings.to_json())?)?;
+    writer.write_all(rest)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Render the pattern as a `width` x `height` lossless WebP and write it to `path`. Few
+/// tools read WebP metadata, so the settings JSON goes in a `.json` file next to it instead.
+pub fn write_webp(
+    path: &Path,
+    settings: &RectSettings,
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn Error>> {
+    let buffer = render_rgba(width, height, settings);
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    image_webp::WebPEncoder::new(&mut writer).encode(
+        &buffer,
+        width,
+        height,
+        image_webp::ColorType::Rgba8,
+    )?;
+    writer.flush()?;
+
+    fs::write(path.with_extension("json"), settings.to_json())?;
+
+    Ok(())
+}
+
+/// A JPEG APP1 segment holding EXIF data with nothing but `comment` as its `UserComment`
+fn exif_user_comment(comment: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    // The comment is stored after the two directories, each of which holds one entry
+    const IFD0_OFFSET: u32 = 8;
+    const EXIF_IFD_OFFSET: u32 = IFD0_OFFSET + 18;
+    const COMMENT_OFFSET: u32 = EXIF_IFD_OFFSET + 18;
+
+    let mut comment_bytes = b"ASCII\0\0\0".to_vec();
+    comment_bytes.extend_from_slice(comment.as_bytes());
+
+    // A little-endian TIFF structure: IFD0 only points to the EXIF directory, which only
+    // has the user comment
+    let mut tiff = b"II*\0".to_vec();
+    tiff.extend_from_slice(&IFD0_OFFSET.to_le_bytes());
+    let mut push_ifd = |tag: u16, kind: u16, count: u32, value: u32| {
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&tag.to_le_bytes());
+        tiff.extend_from_slice(&kind.to_le_bytes());
+        tiff.extend_from_slice(&count.to_le_bytes());
+        tiff.extend_from_slice(&value.to_le_bytes());
+        // There is no next directory
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+    };
+    // The ExifIFD pointer, a LONG
+    push_ifd(0x8769, 4, 1, EXIF_IFD_OFFSET);
+    // The UserComment, UNDEFINED bytes
+    push_ifd(0x9286, 7, comment_bytes.len() as u32, COMMENT_OFFSET);
+    tiff.extend_from_slice(&comment_bytes);
+
+    // The segment length counts itself and the `Exif` header but not the marker
+    let length = u16::try_from(2 + 6 + tiff.len())
+        .map_err(|_| "The settings are too long to fit in the JPEG's EXIF data")?;
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&length.to_be_bytes());
+    segment.extend_from_slice(b"Exif\0\0");
+    segment.extend_from_slice(&tiff);
+    Ok(segment)
+}
+
+/// Write the pattern as a `width` x `height` SVG to `path`, with each dash as a `<line>`
+/// and the settings JSON in a `<metadata>` block. The dashes come from `Pattern`,
+/// so they are in the same places as in the PNG export. The stitches along each axis are
+/// in their own `<g id="horizontal">` or `<g id="vertical">` group, styled with that axis'
+/// color and weight, so each can be edited as a layer. The caption and legend are only
+/// drawn in PNG exports.
+pub fn write_svg(
+    path: &Path,
+    settings: &RectSettings,
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn Error>> {
+    write_svg_to(BufWriter::new(File::create(path)?), settings, width, height)
+}
+
+/// Like `write_svg`, but writes the SVG to `writer` rather than a file
+pub fn write_svg_to(
+    mut writer: impl Write,
+    settings: &RectSettings,
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    )?;
+    writeln!(
+        writer,
+        "<metadata>{}</metadata>",
+        escape_xml(&settings.to_json())
+    )?;
+    writeln!(
+        writer,
+        r#"<rect width="{width}" height="{height}" fill="{}"/>"#,
+        svg_color(settings.background_color),
+    )?;
+
+    // The groups are stacked in the same order as the PNG draws the stitches
+    let pattern = Pattern::new(settings, width, height);
+    let axes = match settings.grid {
+        Grid::Square => [
+            (Axis::Vertical, "vertical"),
+            (Axis::Horizontal, "horizontal"),
+        ],
+        Grid::Triangular | Grid::Polar => [
+            (Axis::Horizontal, "horizontal"),
+            (Axis::Vertical, "vertical"),
+        ],
+    };
+    for (axis, id) in axes {
+        let style = settings.line_style(axis);
+        // Solid stitches leave out the opacity, as SVGs without it are solid already
+        let opacity = if style.opacity < 1.0 {
+            format!(r#" stroke-opacity="{}""#, style.opacity)
+        } else {
+            String::new()
+        };
+        // A gradient runs across the whole image, like in the PNG, rather than along each
+        // stitch on its own
+        let stroke = match style.color_end {
+            Some(end) => {
+                let [x2, y2] = match axis {
+                    Axis::Horizontal => [width, 0],
+                    Axis::Vertical => [0, height],
+                };
+                writeln!(
+                    writer,
+                    r#"<linearGradient id="{id}-gradient" gradientUnits="userSpaceOnUse" x1="0" y1="0" x2="{x2}" y2="{y2}"><stop offset="0" stop-color="{}"/><stop offset="1" stop-color="{}"/></linearGradient>"#,
+                    svg_color(style.color),
+                    svg_color(end)
+                )?;
+                format!("url(#{id}-gradient)")
+            }
+            None => svg_color(style.color),
+        };
+        writeln!(
+            writer,
+            r#"<g id="{id}" stroke="{stroke}" stroke-width="{}"{opacity}>"#,
+            style.weight
+        )?;
+        for stitch in pattern.stitches().filter(|stitch| stitch.axis == axis) {
+            let ([x1, y1], [x2, y2]) = (stitch.start, stitch.end);
+            writeln!(writer, r#"<line x1="{x1}" y1="{y1}" x2="{x2}" y2="{y2}"/>"#)?;
+        }
+        writeln!(writer, "</g>")?;
+    }
+
+    if settings.draw_border {
+        // Like the PNG, keep the whole border inside the image
+        let weight = settings.border_width;
+        writeln!(
+            writer,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="{}" stroke-width="{weight}"/>"#,
+            weight / 2.0,
+            weight / 2.0,
+            width as f32 - weight,
+            height as f32 - weight,
+            svg_color(settings.border_color),
+        )?;
+    }
+
+    writeln!(writer, "</svg>")?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// `color` as an SVG hex color, like `#ff8000`
+fn svg_color(color: Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.red, color.green, color.blue)
+}
+
+/// Escape the characters in `text` that have special meaning in XML
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Undo `escape_xml`. `&amp;` goes last, so that an escaped `&lt;` isn't unescaped twice.
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Read the settings saved in the `<metadata>` block of the SVG at `path`, which
+/// `write_svg` writes
+pub fn load_settings_from_svg(path: &Path) -> Result<RectSettings, Box<dyn Error>> {
+    Ok(settings_from_svg(&fs::read_to_string(path)?)?)
+}
+
+/// Like `load_settings_from_svg`, but reads the settings from the text of the SVG
+pub fn settings_from_svg(svg: &str) -> Result<RectSettings, LoadError> {
+    let metadata = svg
+        .split_once("<metadata>")
+        .and_then(|(_, rest)| rest.split_once("</metadata>"))
+        .ok_or(LoadError::MissingSettings)?
+        .0;
+    RectSettings::from_json(&unescape_xml(metadata))
+}
+
+/// The contents of a `.hito` file: the settings of one pattern, and the version of the
+/// format they were saved in, as pretty-printed JSON
+#[derive(Clone, Serialize)]
+pub struct HitoFile {
+    pub version: u32,
+    pub settings: RectSettings,
+}
+
+impl HitoFile {
+    /// A file holding `settings` in the current format
+    pub fn new(settings: RectSettings) -> Self {
+        HitoFile {
+            version: HITO_VERSION,
+            settings,
+        }
+    }
+
+    /// Serialize the file as pretty-printed JSON, so it diffs nicely under version control
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("The settings can always be serialized")
+    }
+
+    /// Read the JSON written by `to_json`. The settings are loaded like
+    /// `RectSettings::from_json` loads them, once the version is known to be readable.
+    pub fn from_json(json: &str) -> Result<Self, LoadError> {
+        let mut value: serde_json::Value = serde_json::from_str(json).map_err(LoadError::Json)?;
+        let version = value
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or(LoadError::BadField("version"))?;
+        if version > HITO_VERSION as u64 {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let settings = value
+            .get_mut("settings")
+            .map(serde_json::Value::take)
+            .ok_or(LoadError::BadField("settings"))?;
+        Ok(HitoFile {
+            version: version as u32,
+            settings: RectSettings::from_value(settings)?,
+        })
+    }
+}
+
+/// Save `settings` to `path` as a `.hito` file (see `HitoFile`)
+pub fn write_hito(path: &Path, settings: &RectSettings) -> Result<(), Box<dyn Error>> {
+    fs::write(path, HitoFile::new(settings.clone()).to_json())?;
+    Ok(())
+}
+
+/// Read the settings saved in the `.hito` file at `path`
+pub fn load_hito(path: &Path) -> Result<RectSettings, Box<dyn Error>> {
+    Ok(HitoFile::from_json(&fs::read_to_string(path)?)?.settings)
+}
+
+/// Read the settings saved in the `Settings` tEXt chunk of the PNG at `path`
+pub fn load_settings_from_png(path: &Path) -> Result<RectSettings, Box<dyn Error>> {
+    let json = read_settings_chunk(path)?.ok_or(LoadError::MissingSettings)?;
+    Ok(RectSettings::from_json(&json)?)
+}
+
+/// `settings` as a URL query string, like `spacing=25&horz_seed=1&vert_seed=2&brick=true`,
+/// which `settings_from_query` and the `--serve` mode read back. The spacing and seeds are
+/// always given, and every other setting only if it differs from the default. A single
+/// `spacing` stands for both spacings when they are the same. Each value is written as in
+/// the settings JSON (see `RectSettings::to_json`), apart from texts, which are written as
+/// they are, and the selectors, which are only given if they were edited by hand, as bits
+/// (see `selectors_to_bits`).
+pub fn settings_to_query(settings: &RectSettings) -> String {
+    let mut params = if settings.horz_spacing == settings.vert_spacing {
+        vec![format!("spacing={}", settings.horz_spacing)]
+    } else {
+        vec![
+            format!("horz_spacing={}", settings.horz_spacing),
+            format!("vert_spacing={}", settings.vert_spacing),
+        ]
+    };
+    params.push(format!("horz_seed={}", settings.horz_seed));
+    params.push(format!("vert_seed={}", settings.vert_seed));
+    if settings.edited {
+        for (key, selectors) in [
+            ("horz_selectors", &settings.horz_selectors),
+            ("vert_selectors", &settings.vert_selectors),
+        ] {
+            params.push(format!("{key}={}", selectors_to_bits(selectors)));
+        }
+    }
+
+    let (fields, defaults) = (
+        settings_fields(settings),
+        settings_fields(&RectSettings::default()),
+    );
+    for (key, value) in fields {
+        let written = [
+            "horz_spacing",
+            "vert_spacing",
+            "horz_seed",
+            "vert_seed",
+            "horz_selectors",
+            "vert_selectors",
+        ];
+        if written.contains(&key.as_str()) || defaults.get(&key) == Some(&value) {
+            continue;
+        }
+        let value = match value {
+            serde_json::Value::String(text) => text,
+            value => value.to_string(),
+        };
+        params.push(format!("{key}={}", percent_encode(&value)));
+    }
+    params.join("&")
+}
+
+/// The fields of the settings JSON of `settings`. They are read back from the JSON, rather
+/// than converted straight to values, so the numbers are written as short as in the JSON.
+fn settings_fields(settings: &RectSettings) -> serde_json::Map<String, serde_json::Value> {
+    match serde_json::from_str(&settings.to_json()) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => unreachable!("The settings are always saved as a JSON object"),
+    }
+}
+
+/// Read the settings in a query string written by `settings_to_query`. Settings that
+/// aren't in it keep their defaults. Unless the query gives the selectors, they are
+/// generated from the seeds, so call `RectSettings::fit_selectors` before drawing a pattern
+/// bigger than their default count.
+pub fn settings_from_query(query: &str) -> Result<RectSettings, QueryError> {
+    let defaults = settings_fields(&RectSettings::default());
+    let mut fields = serde_json::Map::new();
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (key, value) = param.split_once('=').unwrap_or((param, ""));
+        let invalid = || QueryError::Invalid {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let text = percent_decode(value).ok_or_else(invalid)?;
+        let spacing = || match text.parse::<f32>() {
+            Ok(spacing) if spacing.is_finite() && spacing >= MIN_SPACING => Ok(spacing),
+            _ => Err(invalid()),
+        };
+        let value = match key {
+            "spacing" | "horz_spacing" | "vert_spacing" => spacing()?.into(),
+            "horz_selectors" | "vert_selectors" => {
+                let selectors = selectors_from_bits(&text).map_err(|_| invalid())?;
+                serde_json::Value::from(selectors)
+            }
+            _ => match defaults.get(key) {
+                Some(serde_json::Value::String(_)) => text.into(),
+                Some(_) => serde_json::from_str(&text).map_err(|_| invalid())?,
+                None => return Err(QueryError::Unknown(key.to_string())),
+            },
+        };
+        // Check each setting on its own, so that the error says which one is wrong
+        let mut single = serde_json::Map::new();
+        single.insert(key.to_string(), value.clone());
+        RectSettings::from_value(single.into()).map_err(|_| invalid())?;
+        fields.insert(key.to_string(), value);
+    }
+    Ok(RectSettings::from_value(fields.into()).expect("Each of the settings was already checked"))
+}
+
+/// `text` with every byte that can't go in a URL query value as it is written as `%`
+/// and two hex digits
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b',' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// The text that `percent_encode` encoded as `text`, or `None` if it has a `%` that isn't
+/// followed by two hex digits, or doesn't decode to UTF-8
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = after
+                .get(..2)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            let hex = std::str::from_utf8(hex).expect("Hex digits are ASCII");
+            bytes.push(u8::from_str_radix(hex, 16).expect("The digits were just checked"));
+            rest = &after[2..];
+        } else {
+            bytes.push(byte);
+            rest = after;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The error returned when a query string doesn't describe valid settings
+#[derive(Debug)]
+pub enum QueryError {
+    /// The query has a parameter that isn't a setting
+    Unknown(String),
+    /// The parameter `key` has a value that can't be parsed or is out of range
+    Invalid { key: String, value: String },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Unknown(key) => write!(f, "Unknown parameter {key}"),
+            QueryError::Invalid { key, value } => write!(f, "Invalid value for {key}: {value}"),
+        }
+    }
+}
+
+impl Error for QueryError {}
+
+/// One pattern to render in a batch: the seeds and spacing from one line of a batch file
+/// (see `parse_batch_csv`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchRow {
+    pub horz_seed: u64,
+    pub vert_seed: u64,
+    pub spacing: f32,
+}
+
+impl BatchRow {
+    /// The settings for this row: `settings` with the row's seeds and spacing, and the
+    /// selectors generated from the seeds, even if `settings` took them from text
+    pub fn apply(&self, settings: &RectSettings) -> RectSettings {
+        let mut settings = settings.clone();
+        settings.horz_text.clear();
+        settings.vert_text.clear();
+        settings
+            .with_seeds(self.horz_seed, self.vert_seed)
+            .with_spacing(self.spacing)
+            .build()
+    }
+
+    /// The name of the PNG this row is rendered to, like `seed_3_7.png`
+    pub fn file_name(&self) -> String {
+        format!("seed_{}_{}.png", self.horz_seed, self.vert_seed)
+    }
+}
+
+/// Read a batch file of `horz_seed,vert_seed,spacing` lines, giving each pattern with the
+/// number of the line it's on, counting from 1. Blank lines, and a first line of column
+/// names like `horz_seed,vert_seed,spacing`, are skipped. A line that can't be read doesn't
+/// stop the others from being read.
+pub fn parse_batch_csv(text: &str) -> Vec<(usize, Result<BatchRow, BatchError>)> {
+    text.lines()
+        .enumerate()
+        .filter(|(index, line)| {
+            let is_header = *index == 0 && line.trim_start().starts_with("horz_seed");
+            !line.trim().is_empty() && !is_header
+        })
+        .map(|(index, line)| (index + 1, parse_batch_row(line)))
+        .collect()
+}
+
+fn parse_batch_row(line: &str) -> Result<BatchRow, BatchError> {
+    let columns: Vec<_> = line.split(',').map(str::trim).collect();
+    let [horz_seed, vert_seed, spacing] = columns[..] else {
+        return Err(BatchError::Columns(columns.len()));
+    };
+    let invalid = |column, value: &str| BatchError::Invalid {
+        column,
+        value: value.to_string(),
+    };
+    Ok(BatchRow {
+        horz_seed: horz_seed
+            .parse()
+            .map_err(|_| invalid("horz_seed", horz_seed))?,
+        vert_seed: vert_seed
+            .parse()
+            .map_err(|_| invalid("vert_seed", vert_seed))?,
+        spacing: match spacing.parse::<f32>() {
+            Ok(value) if value.is_finite() && value >= MIN_SPACING => value,
+            _ => return Err(invalid("spacing", spacing)),
+        },
+    })
+}
+
+/// The error for a line of a batch file that isn't a valid `BatchRow`
+#[derive(Debug)]
+pub enum BatchError {
+    /// The line has this many columns instead of three
+    Columns(usize),
+    /// The value in `column` can't be parsed or is out of range
+    Invalid { column: &'static str, value: String },
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchError::Columns(count) => write!(
+                f,
+                "Expected 3 columns (horz_seed,vert_seed,spacing), but found {count}"
+            ),
+            BatchError::Invalid { column, value } => {
+                write!(f, "Invalid value for {column}: {value}")
+            }
+        }
+    }
+}
+
+impl Error for BatchError {}
+
+/// Read every layer saved in the PNG at `path`. PNGs written with a single layer only have
+/// the `Settings` chunk, which is then the only layer.
+pub fn load_layers_from_png(path: &Path) -> Result<Vec<RectSettings>, Box<dyn Error>> {
+    let Some(json) = read_text_chunk(File::open(path)?, "Layers")? else {
+        return Ok(vec![load_settings_from_png(path)?]);
+    };
+    let values: Vec<serde_json::Value> = serde_json::from_str(&json).map_err(LoadError::Json)?;
+    if values.is_empty() {
+        return Err(LoadError::BadField("layers").into());
+    }
+    Ok(values
+        .into_iter()
+        .map(RectSettings::from_value)
+        .collect::<Result<_, _>>()?)
+}
+
+/// Find the text of the `Settings` chunk in the PNG at `path`, if it has one. Besides the
+/// tEXt chunk that `write_png` writes, this reads compressed zTXt chunks and iTXt ones
+/// (compressed or not), which other tools may have saved the settings as.
+pub fn read_settings_chunk(path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    read_settings_chunk_from(File::open(path)?)
+}
+
+/// Like `read_settings_chunk`, but reads the PNG from `reader` rather than a file, such as
+/// a `Cursor` over a PNG that `write_png_to` wrote into memory
+pub fn read_settings_chunk_from(reader: impl Read) -> Result<Option<String>, Box<dyn Error>> {
+    read_text_chunk(reader, "Settings")
+}
+
+/// Find the text of the chunk named `keyword` in the PNG read from `reader`, whichever
+/// kind of text chunk it is (see `read_settings_chunk`)
+fn read_text_chunk(reader: impl Read, keyword: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let decoder = png::Decoder::new(reader);
+    let reader = decoder.read_info()?;
+    let info = reader.info();
+
+    let is_settings = |chunk_keyword: &str| chunk_keyword == keyword;
+    if let Some(chunk) = info
+        .uncompressed_latin1_text
+        .iter()
+        .find(|chunk| is_settings(&chunk.keyword))
+    {
+        return Ok(Some(chunk.text.clone()));
+    }
+    if let Some(chunk) = info
+        .compressed_latin1_text
+        .iter()
+        .find(|chunk| is_settings(&chunk.keyword))
+    {
+        return Ok(Some(chunk.get_text()?));
+    }
+    // The language tag and translated keyword don't matter, since the text is JSON
+    if let Some(chunk) = info
+        .utf8_text
+        .iter()
+        .find(|chunk| is_settings(&chunk.keyword))
+    {
+        return Ok(Some(chunk.get_text()?));
+    }
+    Ok(None)
+}
+
+/// The error returned when saved settings can't be loaded
+#[derive(Debug)]
+pub enum LoadError {
+    /// The PNG has no `Settings` tEXt chunk, or the SVG no `<metadata>` block, e.g. because
+    /// it wasn't exported by this app
+    MissingSettings,
+    /// The settings aren't valid JSON, or a field has a value of the wrong type
+    Json(serde_json::Error),
+    /// The named field has a value that is out of range
+    BadField(&'static str),
+    /// The `.hito` file was saved in a newer version of the format than `HITO_VERSION`
+    UnsupportedVersion(u64),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::MissingSettings => write!(f, "The image has no saved settings"),
+            LoadError::Json(e) => write!(f, "The saved settings are invalid: {e}"),
+            LoadError::BadField(key) => write!(f, "The saved `{key}` setting is invalid"),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "The file is in version {version} of the format, but only versions up to \
+                 {HITO_VERSION} can be read. It was probably saved by a newer release."
+            ),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Render the image that would be exported for `settings` into a freshly allocated
+/// `width` x `height` RGBA buffer
+pub fn render_rgba(width: u32, height: u32, settings: &RectSettings) -> Vec<u8> {
+    render_layers_rgba(width, height, std::slice::from_ref(settings))
+}
+
+/// Like `render_rgba`, but draws the pattern of each of the `layers` in order, each blended
+/// over the ones before it. The caption, legend and export size come from the first layer.
+///
+/// # Panics
+///
+/// If there are no `layers`
+pub fn render_layers_rgba(width: u32, height: u32, layers: &[RectSettings]) -> Vec<u8> {
+    debug_assert!(!layers.is_empty(), "There has to be at least one layer");
+    let settings = &layers[0];
+    // The pattern fills the top of the image, above the legend strip if there is one.
+    // The buffer is stored row by row, so the legend's rows just follow the pattern's.
+    let pattern_height = height.saturating_sub(settings.legend_height());
+    let mut buffer = background_buffer(width, height, settings.background_color);
+    let (pattern, _) = buffer.split_at_mut(width as usize * pattern_height as usize * 4);
+    for layer in layers {
+        draw_pattern_to_image(pattern, width, pattern_height, layer);
+    }
+    draw_decorations(&mut buffer, width, height, settings);
+    buffer
+}
+
+/// Shrink the `width` x `height` RGBA `buffer` so that neither side is longer than
+/// `max_size`, averaging each block of pixels that becomes one. Returns the smaller
+/// buffer and its size. Images that already fit are returned unchanged.
+pub fn downscale_rgba(
+    buffer: &[u8],
+    width: u32,
+    height: u32,
+    max_size: u32,
+) -> (Vec<u8>, u32, u32) {
+    // A whole number of pixels per side of each block keeps the blocks the same shape
+    let factor = width.max(height).div_ceil(max_size.max(1)).max(1);
+    let (small_width, small_height) = (width.div_ceil(factor), height.div_ceil(factor));
+    let mut small = Vec::with_capacity(small_width as usize * small_height as usize * 4);
+    for small_y in 0..small_height {
+        let rows = small_y * factor..((small_y + 1) * factor).min(height);
+        for small_x in 0..small_width {
+            let cols = small_x * factor..((small_x + 1) * factor).min(width);
+            let mut sum = [0u32; 4];
+            for y in rows.clone() {
+                for x in cols.clone() {
+                    let idx = (y as usize * width as usize + x as usize) * 4;
+                    for (total, &channel) in sum.iter_mut().zip(&buffer[idx..idx + 4]) {
+                        *total += channel as u32;
+                    }
+                }
+            }
+            let count = rows.len() as u32 * cols.len() as u32;
+            small.extend(sum.map(|total| ((total + count / 2) / count) as u8));
+        }
+    }
+    (small, small_width, small_height)
+}
+
+/// A `width` x `height` RGBA buffer filled with the opaque `color`
+fn background_buffer(width: u32, height: u32, color: Rgb) -> Vec<u8> {
+    [color.red, color.green, color.blue, 255].repeat(width as usize * height as usize)
+}
+
+/// Render just the pattern for `settings`, without a caption or legend, into a freshly
+/// allocated `width` x `height` RGBA buffer filled with its background color. This is what
+/// the window shows, so it can be checked without a GPU.
+pub fn pattern_pixels(width: u32, height: u32, settings: &RectSettings) -> Vec<u8> {
+    let mut buffer = background_buffer(width, height, settings.background_color);
+    draw_pattern_to_image(&mut buffer, width, height, settings);
+    buffer
+}
+
+/// Render the image that would be exported for `settings` into a caller-owned RGBA
+/// `buffer`, which is cleared first. This lets callers that render repeatedly, like a
+/// preview, reuse one buffer instead of allocating a new one each time.
+pub fn render_into(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    settings: &RectSettings,
+) -> Result<(), BufferSizeError> {
+    let expected = width as usize * height as usize * 4;
+    if buffer.len() != expected {
+        return Err(BufferSizeError {
+            expected,
+            actual: buffer.len(),
+        });
+    }
+
+    // Start from the background color
+    let [red, green, blue] = settings.background_color.into();
+    for pixel in buffer.chunks_exact_mut(4) {
+        pixel.copy_from_slice(&[red, green, blue, 255]);
+    }
+
+    // Like in `render_rgba`, the pattern's rows are the start of the buffer
+    let pattern_height = height.saturating_sub(settings.legend_height());
+    let (pattern, _) = buffer.split_at_mut(width as usize * pattern_height as usize * 4);
+    draw_pattern_to_image(pattern, width, pattern_height, settings);
+    draw_decorations(buffer, width, height, settings);
+
+    Ok(())
+}
+
+/// Draw the caption and legend for `settings` into a `width` x `height` RGBA `buffer` that
+/// the pattern has already been drawn into
+fn draw_decorations(buffer: &mut [u8], width: u32, height: u32, settings: &RectSettings) {
+    let pattern_height = height.saturating_sub(settings.legend_height());
+    let (pattern, _) = buffer.split_at_mut(width as usize * pattern_height as usize * 4);
+    if settings.draw_caption {
+        draw_caption(
+            pattern,
+            width,
+            pattern_height,
+            &settings.caption(),
+            settings.caption_corner,
+            settings.caption_size,
+            settings.caption_color,
+        );
+    }
+
+    if settings.draw_legend {
+        draw_legend(buffer, width, height, pattern_height, settings);
+    }
+}
+
+/// The error returned when an RGBA buffer doesn't match the size of the image to render
+#[derive(Debug)]
+pub struct BufferSizeError {
+    expected: usize,
+    actual: usize,
+}
+
+impl fmt::Display for BufferSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Expected an RGBA buffer of {} bytes, but got {} bytes",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl Error for BufferSizeError {}
+
+/// Draw the hitomezashi pattern described by `settings` into the RGBA `buffer`. This is
+/// the image equivalent of `draw_pattern`: the top left of the image lines up with the top
+/// left of the window.
+pub fn draw_pattern_to_image(buffer: &mut [u8], width: u32, height: u32, settings: &RectSettings) {
+    // Each band of rows is its own slice of the buffer, so the bands can be drawn in
+    // parallel. Each band draws the stitches that reach into it in the same order, so where
+    // stitches overlap the image comes out the same as drawing the whole pattern at once.
+    let pattern = Pattern::new(settings, width, height);
+    let bands = (height as usize).div_ceil(BAND_ROWS as usize);
+    let (weight, antialias) = (settings.line_weight, settings.antialias);
+    let (dashes, stitches) = match settings.grid {
+        Grid::Square => (
+            into_bands(pattern.styled_dashes().into_iter(), bands, |(dash, _)| {
+                dash_pixels(dash, weight, antialias).1
+            }),
+            vec![Vec::new(); bands],
+        ),
+        Grid::Triangular | Grid::Polar => (
+            vec![Vec::new(); bands],
+            into_bands(pattern.stitches(), bands, |stitch| {
+                stitch_pixels(stitch, weight, antialias).1
+            }),
+        ),
+    };
+
+    let band_len = width as usize * BAND_ROWS as usize * 4;
+    buffer
+        .par_chunks_mut(band_len.max(1))
+        .zip(dashes.par_iter().zip(&stitches))
+        .enumerate()
+        .for_each(|(band, (pixels, (dashes, stitches)))| {
+            let first_row = band as i64 * BAND_ROWS as i64;
+            let rows = first_row..first_row + (pixels.len() / (width as usize * 4)) as i64;
+            for (dash, style) in dashes {
+                draw_dash_to_image(pixels, width, height, rows.clone(), dash, *style);
+            }
+            for stitch in stitches {
+                let style = settings.line_style(stitch.axis);
+                draw_stitch_to_image(pixels, width, height, rows.clone(), stitch, style);
+            }
+            if settings.draw_border {
+                draw_border_to_image(pixels, width, height, rows, settings);
+            }
+        });
+}
+
+/// Sort `items` into the `bands` bands of `BAND_ROWS` rows that the rows they cover (as
+/// given by `rows`) reach into, keeping them in order within each band
+fn into_bands<T: Clone>(
+    items: impl Iterator<Item = T>,
+    bands: usize,
+    rows: impl Fn(&T) -> Range<i64>,
+) -> Vec<Vec<T>> {
+    let mut banded = vec![Vec::new(); bands];
+    for item in items {
+        let rows = rows(&item);
+        let band_rows = BAND_ROWS as i64;
+        let first = rows.start.max(0) / band_rows;
+        let last = (rows.end.min(bands as i64 * band_rows) + band_rows - 1) / band_rows;
+        for band in first..last {
+            banded[band as usize].push(item.clone());
+        }
+    }
+    banded
+}
+
+/// Paint the border described by `settings` into `rows` of a `width` x `height` image,
+/// which `buffer` holds just those rows of
+fn draw_border_to_image(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    rows: Range<i64>,
+    settings: &RectSettings,
+) {
+    let weight = settings.border_width.round() as i64;
+    let band_height = (rows.end - rows.start) as u32;
+    let (width, height) = (width as i64, height as i64);
+    for y in rows.clone() {
+        // Rows along the top and bottom edges are all border; the rest only at either end
+        let (left, right) = if y < weight || y + weight >= height {
+            (0..width, 0..0)
+        } else {
+            (0..weight.min(width), (width - weight).max(weight)..width)
+        };
+        for x in left.chain(right) {
+            let color = settings.border_color;
+            set_pixel(buffer, width as u32, band_height, x, y - rows.start, color);
+        }
+    }
+}
+
+/// Paint a single `dash` into `rows` of the RGBA `buffer` as a band of the style's color,
+/// as many pixels thick as its weight and centered on the dash's line. The `buffer` holds
+/// just those rows of the `width` x `height` image.
+fn draw_dash_to_image(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    rows: Range<i64>,
+    dash: &Dash,
+    style: LineStyle,
+) {
+    let (x_edges, y_edges) = dash_edges(dash, style.weight);
+    let (xs, ys) = dash_pixels(dash, style.weight, style.antialias);
+    let band_height = (rows.end - rows.start) as u32;
+    for y in ys.start.max(rows.start)..ys.end.min(rows.end) {
+        for x in xs.clone() {
+            let coverage = if style.antialias {
+                pixel_overlap(x, x_edges) * pixel_overlap(y, y_edges)
+            } else {
+                1.0
+            };
+            let coverage = if style.round_caps {
+                let center = [x as f32 + 0.5, y as f32 + 0.5];
+                let (along, across) = match dash.axis {
+                    Axis::Horizontal => (center[0], center[1]),
+                    Axis::Vertical => (center[1], center[0]),
+                };
+                let (along, across) = (along - dash.start, across - dash.line_pos);
+                coverage * cap_coverage(along, across, dash.end - dash.start, style)
+            } else {
+                coverage
+            };
+            if coverage == 0.0 {
+                continue;
+            }
+            let color = pixel_color(style, dash.axis, x, y, width, height);
+            let y = y - rows.start;
+            let coverage = coverage * style.opacity;
+            blend_pixel(buffer, width, band_height, x, y, color, coverage);
+        }
+    }
+}
+
+/// The color of the stitches drawn with `style` along `axis` at the pixel at (`x`, `y`) of
+/// a `width` x `height` image, which is part way along the gradient if there is one
+fn pixel_color(style: LineStyle, axis: Axis, x: i64, y: i64, width: u32, height: u32) -> Rgb {
+    if style.color_end.is_none() {
+        return style.color;
+    }
+    let center = [x as f32 + 0.5, y as f32 + 0.5];
+    style.color_at(gradient_position(axis, center, width as f32, height as f32))
+}
+
+/// The left and right, and the top and bottom, edges of `dash` when drawn `weight` thick
+fn dash_edges(dash: &Dash, weight: f32) -> ([f32; 2], [f32; 2]) {
+    let across = [dash.line_pos - weight / 2.0, dash.line_pos + weight / 2.0];
+    let along = [dash.start, dash.end];
+    match dash.axis {
+        Axis::Horizontal => (along, across),
+        Axis::Vertical => (across, along),
+    }
+}
+
+/// The columns and rows of the pixels that `dash` covers when drawn `weight` thick. With
+/// `antialias`, that includes every pixel it covers any part of, not just their centers.
+fn dash_pixels(dash: &Dash, weight: f32, antialias: bool) -> (Range<i64>, Range<i64>) {
+    let span = |[start, end]: [f32; 2]| {
+        if antialias {
+            start.floor() as i64..end.ceil() as i64
+        } else {
+            pixel_span(start, end)
+        }
+    };
+    let (x_edges, y_edges) = dash_edges(dash, weight);
+    (span(x_edges), span(y_edges))
+}
+
+/// The columns and rows of the smallest box of pixels that holds `stitch` when drawn
+/// `weight` thick, with room for the blended edges if it is drawn with `antialias`
+fn stitch_pixels(stitch: &Stitch, weight: f32, antialias: bool) -> (Range<i64>, Range<i64>) {
+    let half_weight = weight / 2.0 + if antialias { 0.5 } else { 0.0 };
+    let ([x0, y0], [x1, y1]) = (stitch.start, stitch.end);
+    (
+        pixel_span(x0.min(x1) - half_weight, x0.max(x1) + half_weight),
+        pixel_span(y0.min(y1) - half_weight, y0.max(y1) + half_weight),
+    )
+}
+
+/// How much of the pixels in column (or row) `pixel` lie between `start` and `end`
+fn pixel_overlap(pixel: i64, [start, end]: [f32; 2]) -> f32 {
+    (end.min(pixel as f32 + 1.0) - start.max(pixel as f32)).max(0.0)
+}
+
+/// Paint a single `stitch` into `rows` of the RGBA `buffer` as a band of the style's
+/// color, as many pixels thick as its weight. Like `draw_dash_to_image`, a pixel is painted
+/// if its center is inside the band.
+fn draw_stitch_to_image(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    rows: Range<i64>,
+    stitch: &Stitch,
+    style: LineStyle,
+) {
+    let length = stitch.length();
+    if length == 0.0 {
+        return;
+    }
+    let half_weight = style.weight / 2.0;
+    let [x0, y0] = stitch.start;
+    let direction = [(stitch.end[0] - x0) / length, (stitch.end[1] - y0) / length];
+    let (xs, ys) = stitch_pixels(stitch, style.weight, style.antialias);
+    let band_height = (rows.end - rows.start) as u32;
+    for y in ys.start.max(rows.start)..ys.end.min(rows.end) {
+        for x in xs.clone() {
+            let center = [x as f32 + 0.5 - x0, y as f32 + 0.5 - y0];
+            let along = center[0] * direction[0] + center[1] * direction[1];
+            let across = cross(direction, center);
+            let coverage = if style.antialias {
+                // Roughly how much of the pixel is inside, from how far its center is from
+                // each edge of the stitch
+                let inside = |distance: f32| (distance + 0.5).clamp(0.0, 1.0);
+                inside(along).min(inside(length - along)) * inside(half_weight - across.abs())
+            } else if (0.0..length).contains(&along)
+                && (-half_weight..half_weight).contains(&across)
+            {
+                1.0
+            } else {
+                continue;
+            };
+            let coverage = if style.round_caps {
+                coverage * cap_coverage(along, across, length, style)
+            } else {
+                coverage
+            };
+            if coverage == 0.0 {
+                continue;
+            }
+            let color = pixel_color(style, stitch.axis, x, y, width, height);
+            let y = y - rows.start;
+            let coverage = coverage * style.opacity;
+            blend_pixel(buffer, width, band_height, x, y, color, coverage);
+        }
+    }
+}
+
+/// How much of a pixel of a stitch drawn with `style.round_caps` is left in by the rounded
+/// ends, from how far its center is `along` the stitch from its start and `across` the
+/// stitch from its middle. Each end is a half circle as wide as the stitch, drawn inside
+/// the stitch's length, so everything more than half the weight from either end is kept.
+fn cap_coverage(along: f32, across: f32, length: f32, style: LineStyle) -> f32 {
+    let half_weight = style.weight / 2.0;
+    let nearest = if length < style.weight {
+        length / 2.0
+    } else {
+        along.clamp(half_weight, length - half_weight)
+    };
+    let distance = (along - nearest).hypot(across);
+    if style.antialias {
+        (half_weight - distance + 0.5).clamp(0.0, 1.0)
+    } else if distance < half_weight {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// The pixels whose centers lie in `start..end`, which are the ones the live view fills for
+/// the same span. Rounding each end on its own would shift spans that start or end on a half
+/// pixel, such as the grid points of a 7.5 spacing, off the grid.
+fn pixel_span(start: f32, end: f32) -> Range<i64> {
+    (start - 0.5).ceil() as i64..(end - 0.5).ceil() as i64
+}
+
+/// The direction a line of the pattern runs in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+/// A single dash of the pattern, in image coordinates: the origin is the top left corner
+/// and y increases downwards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dash {
+    pub axis: Axis,
+    /// Where the dash's line sits across the image: its y for horizontal lines, its x for
+    /// vertical lines
+    pub line_pos: f32,
+    /// Where the dash starts along its line
+    pub start: f32,
+    /// Where the dash ends along its line
+    pub end: f32,
+}
+
+impl Dash {
+    pub fn length(&self) -> f32 {
+        self.end - self.start
+    }
+}
+
+/// A single stitch of the pattern as a line segment in image coordinates. Unlike a `Dash`,
+/// it can run at an angle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stitch {
+    /// Whose line style the stitch is drawn in. The lines of a triangular grid that run at
+    /// an angle are drawn like vertical lines.
+    pub axis: Axis,
+    pub start: [f32; 2],
+    pub end: [f32; 2],
+}
+
+impl Stitch {
+    pub fn length(&self) -> f32 {
+        (self.end[0] - self.start[0]).hypot(self.end[1] - self.start[1])
+    }
+}
+
+impl From<Dash> for Stitch {
+    fn from(dash: Dash) -> Self {
+        let (start, end) = match dash.axis {
+            Axis::Horizontal => ([dash.start, dash.line_pos], [dash.end, dash.line_pos]),
+            Axis::Vertical => ([dash.line_pos, dash.start], [dash.line_pos, dash.end]),
+        };
+        Stitch {
+            axis: dash.axis,
+            start,
+            end,
+        }
+    }
+}
+
+/// The height of an equilateral triangle with sides of 1
+const TRIANGLE_HEIGHT: f32 = 0.866_025_4;
+
+/// One of the three families of parallel lines of a triangular grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TriangleFamily {
+    /// Moving by `across` times the spacing goes from one line of the family to the next,
+    /// and moving by `along` times the spacing goes from one corner to the next along a line
+    across: [f32; 2],
+    along: [f32; 2],
+    /// Which of the settings' selectors the lines take, and whose style they are drawn in
+    selectors: usize,
+    axis: Axis,
+}
+
+/// The horizontal lines, the lines running down to the right, and the lines running down
+/// to the left. Line 0 of each passes through the top left corner of the grid.
+const TRIANGLE_FAMILIES: [TriangleFamily; 3] = [
+    TriangleFamily {
+        across: [0.5, TRIANGLE_HEIGHT],
+        along: [1.0, 0.0],
+        selectors: 0,
+        axis: Axis::Horizontal,
+    },
+    TriangleFamily {
+        across: [1.0, 0.0],
+        along: [0.5, TRIANGLE_HEIGHT],
+        selectors: 1,
+        axis: Axis::Vertical,
+    },
+    TriangleFamily {
+        across: [1.0, 0.0],
+        along: [-0.5, TRIANGLE_HEIGHT],
+        selectors: 2,
+        axis: Axis::Vertical,
+    },
+];
+
+/// The furthest apart, in pixels, that `ArcDash::points` puts its points
+const ARC_STEP: f32 = 2.0;
+
+/// The rings and spokes of a polar grid fitted to an image (see `Pattern::polar_grid`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PolarGrid {
+    /// Where the rings are centered, in image coordinates
+    pub center: [f32; 2],
+    /// The distance between rings
+    pub ring_spacing: f32,
+    /// How many rings there are around the center, not counting the center itself
+    pub rings: i64,
+    pub spokes: i64,
+}
+
+/// A single stitch along a ring of a polar grid, in image coordinates. The angles are in
+/// radians from the direction of the x axis, increasing clockwise since y increases
+/// downwards.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArcDash {
+    pub center: [f32; 2],
+    pub radius: f32,
+    pub start_angle: f32,
+    pub end_angle: f32,
+}
+
+impl ArcDash {
+    pub fn length(&self) -> f32 {
+        self.radius * (self.end_angle - self.start_angle)
+    }
+
+    /// Points along the arc from its start to its end, close enough together that the
+    /// straight lines between them look like the arc
+    pub fn points(&self) -> impl Iterator<Item = [f32; 2]> {
+        let arc = *self;
+        let steps = (arc.length() / ARC_STEP).ceil().max(1.0) as usize;
+        (0..=steps).map(move |step| {
+            let angle =
+                arc.start_angle + (arc.end_angle - arc.start_angle) * step as f32 / steps as f32;
+            let (sin, cos) = angle.sin_cos();
+            [
+                arc.center[0] + arc.radius * cos,
+                arc.center[1] + arc.radius * sin,
+            ]
+        })
+    }
+}
+
+/// The z component of the cross product of `a` and `b`
+fn cross(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+/// Whether any of the line segment from `start` to `end` is inside the rectangle from
+/// `area[0]`, its top left corner, to `area[1]`, its bottom right one. The segment is cut
+/// down to the part between each pair of sides in turn, and crosses if anything is left.
+fn segment_crosses(start: [f32; 2], end: [f32; 2], area: [[f32; 2]; 2]) -> bool {
+    // How far along the segment, from 0.0 at `start` to 1.0 at `end`, it is inside
+    let (mut enter, mut leave) = (0.0_f32, 1.0_f32);
+    for axis in 0..2 {
+        let (from, delta) = (start[axis], end[axis] - start[axis]);
+        let (low, high) = (area[0][axis], area[1][axis]);
+        if delta == 0.0 {
+            if !(low..=high).contains(&from) {
+                return false;
+            }
+            continue;
+        }
+        let (a, b) = ((low - from) / delta, (high - from) / delta);
+        enter = enter.max(a.min(b));
+        leave = leave.min(a.max(b));
+    }
+    enter <= leave
+}
+
+/// Where the line through `base` in direction `direction` is inside a `width` x `height`
+/// rectangle, as the range of `t` for which `base + t * direction` is inside it
+fn clip_line(base: [f32; 2], direction: [f32; 2], width: f32, height: f32) -> Option<(f32, f32)> {
+    let (mut t_min, mut t_max) = (f32::NEG_INFINITY, f32::INFINITY);
+    for (base, direction, extent) in [
+        (base[0], direction[0], width),
+        (base[1], direction[1], height),
+    ] {
+        if direction == 0.0 {
+            if base < 0.0 || base > extent {
+                return None;
+            }
+        } else {
+            let (a, b) = (-base / direction, (extent - base) / direction);
+            t_min = t_min.max(a.min(b));
+            t_max = t_max.min(a.max(b));
+        }
+    }
+    (t_min < t_max).then_some((t_min, t_max))
+}
+
+/// The geometry of the pattern described by some settings over a `width` x `height`
+/// image. This is what everything that needs to know where the stitches go shares.
+#[derive(Clone, Copy)]
+pub struct Pattern<'a> {
+    settings: &'a RectSettings,
+    /// The size of the area inside the margin, which the stitches are laid out in
+    width: u32,
+    height: u32,
+    margin: u32,
+}
+
+impl<'a> Pattern<'a> {
+    pub fn new(settings: &'a RectSettings, width: u32, height: u32) -> Self {
+        let margin = if settings.tileable {
+            0
+        } else {
+            (settings.margin.max(0.0).round() as u32).min(width.min(height) / 2)
+        };
+        Pattern {
+            settings,
+            width: width - 2 * margin,
+            height: height - 2 * margin,
+            margin,
+        }
+    }
+
+    /// How far in from each edge of the image the stitches are kept, in pixels (see
+    /// `RectSettings::margin`)
+    pub fn margin(&self) -> f32 {
+        self.margin as f32
+    }
+
+    /// Every dash of the pattern on a square grid, vertical lines first (left to right) and
+    /// then horizontal lines (top to bottom). The dashes over the cells at the edges of the
+    /// image are cut short at them.
+    ///
+    /// If the pattern is `tileable`, only as many selectors are used as divide the number
+    /// of lines evenly, so that the next tile starts where this one left off. The lines on
+    /// the right and bottom edges, which belong to the next tile, are included too, since
+    /// half of each of their stitches falls inside this one.
+    pub fn dashes(&self) -> impl Iterator<Item = Dash> + 'a {
+        let settings = self.settings;
+        let tileable = settings.tileable;
+        let (width, height) = (self.width as f32, self.height as f32);
+
+        let vert_gaps = settings.line_gaps(Axis::Vertical);
+        let horz_gaps = settings.line_gaps(Axis::Horizontal);
+        let vert_lines = line_positions(vert_gaps, 0.0, width, false).count();
+        let vert_selectors = &settings.vert_selectors
+            [..selector_period(settings.vert_selectors.len(), vert_lines, tileable)];
+        let horz_lines = line_positions(horz_gaps, 0.0, height, false).count();
+        let horz_selectors = &settings.horz_selectors
+            [..selector_period(settings.horz_selectors.len(), horz_lines, tileable)];
+
+        // The cells along each line are as long as the spacing between the lines that cross
+        // it, so that every cell starts and ends on a grid point. The stitches cover the first
+        // `dash_ratio` of each of their cell's `dashes_per_cell` parts
+        let (ratio, count) = (self.dash_ratio(), self.dashes_per_cell());
+        let (offset_x, offset_y) = settings.grid_offset();
+        let margin = self.margin();
+        let vertical =
+            line_positions(vert_gaps, offset_x, width, tileable).flat_map(move |(idx, x)| {
+                let starts_on = selector_at(vert_selectors, idx);
+                line_spans(
+                    settings.dashed,
+                    starts_on,
+                    horz_gaps,
+                    offset_y,
+                    height,
+                    ratio,
+                    count,
+                )
+                .map(move |(start, end)| Dash {
+                    axis: Axis::Vertical,
+                    line_pos: margin + x,
+                    start: margin + start,
+                    end: margin + end,
+                })
+            });
+
+        let horizontal =
+            line_positions(horz_gaps, offset_y, height, tileable).flat_map(move |(idx, y)| {
+                let starts_on = brick_starts_on(horz_selectors, idx, settings.brick);
+                line_spans(
+                    settings.dashed,
+                    starts_on,
+                    vert_gaps,
+                    offset_x,
+                    width,
+                    ratio,
+                    count,
+                )
+                .map(move |(start, end)| Dash {
+                    axis: Axis::Horizontal,
+                    line_pos: margin + y,
+                    start: margin + start,
+                    end: margin + end,
+                })
+            });
+
+        vertical.chain(horizontal)
+    }
+
+    /// Which loop each of the `dashes` of a square grid is part of, in the same order,
+    /// and how many loops there are. The loops are the pieces that the stitches joined end
+    /// to end at the grid points form, like in `count_loops`, but of the dashes as drawn,
+    /// so they stop at the edges of the image. They are numbered from 0 in the order their
+    /// first dashes come up.
+    pub fn dash_loops(&self) -> (Vec<usize>, usize) {
+        let settings = self.settings;
+        let (offset_x, offset_y) = settings.grid_offset();
+        let (vert_gaps, horz_gaps) = (
+            settings.line_gaps(Axis::Vertical),
+            settings.line_gaps(Axis::Horizontal),
+        );
+        let margin = self.margin();
+        // The numbers of the vertical and horizontal lines that meet at each end of a dash.
+        // A dash sits on its line, and its middle is inside the cell it stitches.
+        let ends = |dash: &Dash| {
+            let middle = (dash.start + dash.end) / 2.0 - margin;
+            let across = dash.line_pos - margin;
+            match dash.axis {
+                Axis::Vertical => {
+                    let col = line_at_or_before(vert_gaps, offset_x, across);
+                    let row = line_at_or_before(horz_gaps, offset_y, middle);
+                    [(col, row), (col, row + 1)]
+                }
+                Axis::Horizontal => {
+                    let row = line_at_or_before(horz_gaps, offset_y, across);
+                    let col = line_at_or_before(vert_gaps, offset_x, middle);
+                    [(col, row), (col + 1, row)]
+                }
+            }
+        };
+
+        let mut points = HashMap::new();
+        let dash_points: Vec<[usize; 2]> = self
+            .dashes()
+            .map(|dash| {
+                ends(&dash).map(|end| {
+                    let next = points.len();
+                    *points.entry(end).or_insert(next)
+                })
+            })
+            .collect();
+        let mut pieces = Pieces::new(points.len());
+        for &[a, b] in &dash_points {
+            pieces.join(a, b);
+        }
+
+        let mut numbers = vec![None; pieces.len()];
+        let mut count = 0;
+        let loops = dash_points
+            .iter()
+            .map(|&[a, _]| {
+                let root = pieces.root(a);
+                *numbers[root].get_or_insert_with(|| {
+                    count += 1;
+                    count - 1
+                })
+            })
+            .collect();
+        (loops, count)
+    }
+
+    /// Every dash of a square grid (see `dashes`) with the style it is drawn in. That is
+    /// the style of its line, or with `color_by_loop`, the color of its loop.
+    pub fn styled_dashes(&self) -> Vec<(Dash, LineStyle)> {
+        let settings = self.settings;
+        let loops = settings.color_by_loop.then(|| self.dash_loops().0);
+        self.dashes()
+            .enumerate()
+            .map(|(index, dash)| {
+                let mut style = settings.line_style(dash.axis);
+                if let Some(loops) = &loops {
+                    style.color = LOOP_PALETTE[loops[index] % LOOP_PALETTE.len()];
+                    style.color_end = None;
+                }
+                (dash, style)
+            })
+            .collect()
+    }
+
+    /// Whether cell number `cell` along a line whose cell 0 is stitched if `starts_on` is
+    /// stitched. The cells alternate between stitches and gaps, unless the pattern isn't
+    /// `dashed`.
+    fn is_stitched(&self, cell: i64, starts_on: bool) -> bool {
+        !self.settings.dashed || (cell.rem_euclid(2) == 0) == starts_on
+    }
+
+    /// How much of each stitched cell the stitch covers. Solid lines cover all of it.
+    fn dash_ratio(&self) -> f32 {
+        if self.settings.dashed {
+            self.settings.dash_ratio
+        } else {
+            1.0
+        }
+    }
+
+    /// How many dashes each stitched cell is split into. Solid lines aren't split.
+    fn dashes_per_cell(&self) -> u32 {
+        if self.settings.dashed {
+            self.settings.dashes_per_cell
+        } else {
+            1
+        }
+    }
+
+    /// Every stitch of the pattern, on whichever grid the settings use
+    pub fn stitches(&self) -> Box<dyn Iterator<Item = Stitch> + 'a> {
+        match self.settings.grid {
+            Grid::Square => Box::new(self.dashes().map(Stitch::from)),
+            Grid::Triangular => Box::new(self.triangle_stitches()),
+            Grid::Polar => Box::new(self.polar_stitches()),
+        }
+    }
+
+    /// Every stitch of the pattern on a triangular grid, one family of lines after
+    /// another. The stitches are cut off at the edges of the image. The grid isn't shifted
+    /// for tileable patterns, but doesn't tile either.
+    pub fn triangle_stitches(&self) -> impl Iterator<Item = Stitch> + 'a {
+        let pattern = *self;
+        let settings = self.settings;
+        let spacing = settings.spacing(Axis::Horizontal);
+        let (width, height) = (self.width as f32, self.height as f32);
+        let (offset_x, offset_y) = settings.grid_offset();
+        let (ratio, count) = (self.dash_ratio(), self.dashes_per_cell());
+
+        TRIANGLE_FAMILIES.into_iter().flat_map(move |family| {
+            let selectors = match family.selectors {
+                0 => &settings.horz_selectors,
+                1 => &settings.vert_selectors,
+                _ => &settings.diag_selectors,
+            };
+            let along = family.along.map(|a| a * spacing);
+            pattern.triangle_lines(family).flat_map(move |line| {
+                let starts_on = selector_at(selectors, line);
+                let base = [
+                    offset_x + line as f32 * spacing * family.across[0],
+                    offset_y + line as f32 * spacing * family.across[1],
+                ];
+                let at = move |t: f32| {
+                    let margin = pattern.margin();
+                    [
+                        margin + base[0] + t * along[0],
+                        margin + base[1] + t * along[1],
+                    ]
+                };
+                // Like on a square grid, the cells along the line alternate between stitches
+                // and gaps, starting from the corner at `base`
+                let (t_min, t_max) = clip_line(base, along, width, height).unwrap_or((0.0, 0.0));
+                (t_min.floor() as i64..t_max.ceil() as i64)
+                    .filter(move |&cell| pattern.is_stitched(cell, starts_on))
+                    .flat_map(move |cell| cell_dashes(cell as f32, cell as f32 + 1.0, ratio, count))
+                    .filter_map(move |(start, end)| {
+                        let (start, end) = (start.max(t_min), end.min(t_max));
+                        (start < end).then(|| Stitch {
+                            axis: family.axis,
+                            start: at(start),
+                            end: at(end),
+                        })
+                    })
+            })
+        })
+    }
+
+    /// The numbers of the lines that cross the image and take their selectors from `axis`
+    /// (see `selector_at`). On a triangular grid, the vertical selectors are for the lines
+    /// running down to the right.
+    pub fn line_numbers(&self, axis: Axis) -> Range<i64> {
+        let settings = self.settings;
+        let lines = match settings.grid {
+            Grid::Square => {
+                let (offset_x, offset_y) = settings.grid_offset();
+                let (offset, extent) = match axis {
+                    Axis::Horizontal => (offset_y, self.height),
+                    Axis::Vertical => (offset_x, self.width),
+                };
+                let gaps = settings.line_gaps(axis);
+                let first = first_line(gaps, offset);
+                let count = line_positions(gaps, offset, extent as f32, settings.tileable).count();
+                first..=first + count as i64 - 1
+            }
+            Grid::Triangular => self.triangle_lines(match axis {
+                Axis::Horizontal => TRIANGLE_FAMILIES[0],
+                Axis::Vertical => TRIANGLE_FAMILIES[1],
+            }),
+            Grid::Polar => {
+                let grid = self.polar_grid();
+                match axis {
+                    Axis::Horizontal => 1..=grid.rings,
+                    Axis::Vertical => 0..=grid.spokes - 1,
+                }
+            }
+        };
+        *lines.start()..*lines.end() + 1
+    }
+
+    /// The numbers of the lines that take their selectors from `axis`, like `line_numbers`,
+    /// and cross the part of the image from `area[0]`, its top left corner, to `area[1]`,
+    /// its bottom right one, such as the part of the live view on screen when it is zoomed
+    pub fn line_numbers_in(&self, axis: Axis, area: [[f32; 2]; 2]) -> Vec<i64> {
+        let settings = self.settings;
+        let margin = self.margin();
+        let [[left, top], [right, bottom]] = area;
+        let lines = self.line_numbers(axis);
+        match settings.grid {
+            Grid::Square => {
+                let (offset_x, offset_y) = settings.grid_offset();
+                let (offset, low, high) = match axis {
+                    Axis::Horizontal => (offset_y, top, bottom),
+                    Axis::Vertical => (offset_x, left, right),
+                };
+                let gaps = settings.line_gaps(axis);
+                let (low, high) = (low - margin, high - margin);
+                lines
+                    .filter(|&line| (low..=high).contains(&(offset + line_distance(gaps, line))))
+                    .collect()
+            }
+            Grid::Triangular => {
+                let family = match axis {
+                    Axis::Horizontal => TRIANGLE_FAMILIES[0],
+                    Axis::Vertical => TRIANGLE_FAMILIES[1],
+                };
+                let area = [
+                    [left - margin, top - margin],
+                    [right - margin, bottom - margin],
+                ];
+                let crossing = self.triangle_lines_in(family, area);
+                lines.filter(|line| crossing.contains(line)).collect()
+            }
+            Grid::Polar => {
+                let grid = self.polar_grid();
+                let [x, y] = grid.center;
+                match axis {
+                    // A ring crosses the area if it is further out than the nearest point of
+                    // the area and not as far as the furthest corner
+                    Axis::Horizontal => {
+                        let nearest = (x.clamp(left, right) - x).hypot(y.clamp(top, bottom) - y);
+                        let furthest = (x - left)
+                            .abs()
+                            .max((x - right).abs())
+                            .hypot((y - top).abs().max((y - bottom).abs()));
+                        lines
+                            .filter(|&ring| {
+                                let radius = ring as f32 * grid.ring_spacing;
+                                (nearest..=furthest).contains(&radius)
+                            })
+                            .collect()
+                    }
+                    // The spokes run out from the first ring to the outer one (see
+                    // `polar_spokes`)
+                    Axis::Vertical => {
+                        let cell_angle = std::f32::consts::TAU / grid.spokes as f32;
+                        let (inner, outer) =
+                            (grid.ring_spacing, grid.rings as f32 * grid.ring_spacing);
+                        lines
+                            .filter(|&spoke| {
+                                let (sin, cos) = (spoke as f32 * cell_angle).sin_cos();
+                                let at = |radius: f32| [x + radius * cos, y + radius * sin];
+                                segment_crosses(at(inner), at(outer), area)
+                            })
+                            .collect()
+                    }
+                }
+            }
+        }
+    }
+
+    /// The numbers of the lines of `family` that cross the image
+    fn triangle_lines(&self, family: TriangleFamily) -> RangeInclusive<i64> {
+        let (width, height) = (self.width as f32, self.height as f32);
+        self.triangle_lines_in(family, [[0.0, 0.0], [width, height]])
+    }
+
+    /// The numbers of the lines of `family` that cross the rectangle from `area[0]`, its top
+    /// left corner, to `area[1]`, its bottom right one, inside the margin
+    fn triangle_lines_in(
+        &self,
+        family: TriangleFamily,
+        area: [[f32; 2]; 2],
+    ) -> RangeInclusive<i64> {
+        let spacing = self.settings.spacing(Axis::Horizontal);
+        let (offset_x, offset_y) = self.settings.grid_offset();
+        let [[left, top], [right, bottom]] = area;
+        // Which line each corner of the area is on, counting fractions of the spacing
+        let denominator = spacing * cross(family.across, family.along);
+        let lines = [[left, top], [right, top], [left, bottom], [right, bottom]]
+            .map(|[x, y]| cross([x - offset_x, y - offset_y], family.along) / denominator);
+        let first = lines.iter().copied().fold(f32::INFINITY, f32::min);
+        let last = lines.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        first.ceil() as i64..=last.floor() as i64
+    }
+
+    /// The rings and spokes of the pattern on a polar grid
+    pub fn polar_grid(&self) -> PolarGrid {
+        let settings = self.settings;
+        let (width, height) = (self.width as f32, self.height as f32);
+        let (offset_x, offset_y) = settings.grid_offset();
+        let ring_spacing = settings.spacing(Axis::Horizontal);
+        // The outer ring is the largest that fits in the image
+        let rings = (width.min(height) / 2.0 / ring_spacing).floor() as i64;
+        // Around the outer ring, the cells are about as long as the vertical spacing. There
+        // is always an even number of them, so the stitches and gaps alternate all the way
+        // around.
+        let outer_circumference = std::f32::consts::TAU * rings as f32 * ring_spacing;
+        let pairs = (outer_circumference / (2.0 * settings.spacing(Axis::Vertical))).round();
+        let margin = self.margin();
+        PolarGrid {
+            center: [
+                margin + width / 2.0 + offset_x,
+                margin + height / 2.0 + offset_y,
+            ],
+            ring_spacing,
+            rings,
+            spokes: 2 * (pairs as i64).max(2),
+        }
+    }
+
+    /// Every stitch along the rings of the pattern on a polar grid, from the innermost
+    /// ring out. Ring `n`, `n` times the horizontal spacing from the center, takes
+    /// horizontal selector `n`, and its cells run clockwise from the spoke pointing right.
+    pub fn polar_arcs(&self) -> impl Iterator<Item = ArcDash> + 'a {
+        let pattern = *self;
+        let settings = self.settings;
+        let grid = self.polar_grid();
+        let cell_angle = std::f32::consts::TAU / grid.spokes as f32;
+        let (ratio, count) = (self.dash_ratio(), self.dashes_per_cell());
+        (1..=grid.rings).flat_map(move |ring| {
+            let starts_on = selector_at(&settings.horz_selectors, ring);
+            (0..grid.spokes)
+                .filter(move |&cell| pattern.is_stitched(cell, starts_on))
+                .flat_map(move |cell| {
+                    let start_angle = cell as f32 * cell_angle;
+                    cell_dashes(start_angle, start_angle + cell_angle, ratio, count)
+                })
+                .map(move |(start_angle, end_angle)| ArcDash {
+                    center: grid.center,
+                    radius: ring as f32 * grid.ring_spacing,
+                    start_angle,
+                    end_angle,
+                })
+        })
+    }
+
+    /// Every stitch along the spokes of the pattern on a polar grid, clockwise from the
+    /// spoke pointing right. Spoke `n` takes vertical selector `n`, and its cells run out
+    /// from the first ring to the outer ring, numbered by the ring they start on. Inside the
+    /// first ring the spokes would only crowd together.
+    pub fn polar_spokes(&self) -> impl Iterator<Item = Stitch> + 'a {
+        let pattern = *self;
+        let settings = self.settings;
+        let grid = self.polar_grid();
+        let cell_angle = std::f32::consts::TAU / grid.spokes as f32;
+        let (ratio, count) = (self.dash_ratio(), self.dashes_per_cell());
+        (0..grid.spokes).flat_map(move |spoke| {
+            let starts_on = selector_at(&settings.vert_selectors, spoke);
+            let (sin, cos) = (spoke as f32 * cell_angle).sin_cos();
+            let at =
+                move |radius: f32| [grid.center[0] + radius * cos, grid.center[1] + radius * sin];
+            (1..grid.rings)
+                .filter(move |&cell| pattern.is_stitched(cell, starts_on))
+                .flat_map(move |cell| {
+                    let start = cell as f32 * grid.ring_spacing;
+                    cell_dashes(start, start + grid.ring_spacing, ratio, count)
+                })
+                .map(move |(start, end)| Stitch {
+                    axis: Axis::Vertical,
+                    start: at(start),
+                    end: at(end),
+                })
+        })
+    }
+
+    /// Every stitch of the pattern on a polar grid, the rings and then the spokes. Each arc
+    /// of a ring is split into short straight stitches (see `ArcDash::points`), so it can
+    /// be drawn like the stitches of the other grids.
+    pub fn polar_stitches(&self) -> impl Iterator<Item = Stitch> + 'a {
+        let arcs = self.polar_arcs().flat_map(|arc| {
+            let points: Vec<[f32; 2]> = arc.points().collect();
+            (1..points.len()).map(move |i| Stitch {
+                axis: Axis::Horizontal,
+                start: points[i - 1],
+                end: points[i],
+            })
+        });
+        arcs.chain(self.polar_spokes())
+    }
+
+    /// The total length of all the stitches, in pixels
+    pub fn thread_length(&self) -> f32 {
+        self.stitches().map(|stitch| stitch.length()).sum()
+    }
+}
+
+/// The number of the last line at or before `pos`, when line 0 is at `offset` and the gaps
+/// between lines are `gaps` (see `line_distance`)
+pub fn line_at_or_before(gaps: (f32, f32), offset: f32, pos: f32) -> i64 {
+    let line = first_line(gaps, offset - pos);
+    if offset + line_distance(gaps, line) > pos {
+        line - 1
+    } else {
+        line
+    }
+}
+
+/// How far line `idx` is from line 0 when the gaps between lines alternate between `gaps.0`
+/// (after even-numbered lines) and `gaps.1` (after odd-numbered ones)
+pub fn line_distance(gaps: (f32, f32), idx: i64) -> f32 {
+    let (even, odd) = gaps;
+    if even == odd {
+        return idx as f32 * even;
+    }
+    let pairs = idx.div_euclid(2) as f32 * (even + odd);
+    if idx.rem_euclid(2) == 1 {
+        pairs + even
+    } else {
+        pairs
+    }
+}
+
+/// The number of the first line at or after 0, when line 0 is at `offset` and the gaps
+/// between lines are `gaps` (see `line_distance`)
+pub fn first_line(gaps: (f32, f32), offset: f32) -> i64 {
+    let (even, odd) = gaps;
+    if even == odd {
+        return (-offset / even).ceil() as i64;
+    }
+    // Start from the even line in the pair before 0, and step forward
+    let mut idx = 2 * (-offset / (even + odd)).floor() as i64;
+    while offset + line_distance(gaps, idx) < 0.0 {
+        idx += 1;
+    }
+    idx
+}
+
+/// The positions of lines `gaps` apart (see `line_distance`), starting at the first one at
+/// or after 0 and stopping before `extent`, or at `extent` if `include_end`. Line 0 sits at
+/// `offset`, and each position comes with the number of its line, which is negative for
+/// lines before it.
+fn line_positions(
+    gaps: (f32, f32),
+    offset: f32,
+    extent: f32,
+    include_end: bool,
+) -> impl Iterator<Item = (i64, f32)> {
+    (first_line(gaps, offset)..)
+        .map(move |idx| (idx, offset + line_distance(gaps, idx)))
+        .take_while(move |(_, pos)| *pos < extent || (include_end && *pos <= extent))
+}
+
+/// The `(start, end)` of each dash along a line of length `extent`, where the cells between
+/// the lines crossing it are `gaps` apart (see `line_distance`), starting from the one at
+/// `offset`. If `starts_on` that cell is a dash, otherwise it is a gap, and after it the
+/// cells alternate. Each dash is split up like `cell_dashes`, and the dashes that only
+/// partly fit on the line are cut short at 0 and `extent`.
+fn dash_spans(
+    starts_on: bool,
+    gaps: (f32, f32),
+    offset: f32,
+    extent: f32,
+    ratio: f32,
+    count: u32,
+) -> impl Iterator<Item = (f32, f32)> {
+    // Cells after even-numbered lines are `gaps.0` long and the others `gaps.1`, so every
+    // dash is as long as the first one
+    let (even, odd) = gaps;
+    let (first, dash_length) = if starts_on {
+        (offset, even)
+    } else {
+        (offset + even, odd)
+    };
+    let period = even + odd;
+    // The first dash that ends after 0
+    let first_idx = ((-first - dash_length) / period).floor() as i64 + 1;
+    (first_idx..)
+        .map(move |idx| first + idx as f32 * period)
+        .take_while(move |&start| start < extent)
+        // Split the whole dash before cutting it, so its parts stay where they would be
+        .flat_map(move |start| cell_dashes(start, start + dash_length, ratio, count))
+        .map(move |(start, end)| (start.max(0.0), end.min(extent)))
+        .filter(|(start, end)| end > start)
+}
+
+/// The `(start, end)` of each stitch along a line of a square grid: its dashes if it is
+/// `dashed` (see `dash_spans`), otherwise the whole line, split up like `cell_dashes`
+fn line_spans(
+    dashed: bool,
+    starts_on: bool,
+    gaps: (f32, f32),
+    offset: f32,
+    extent: f32,
+    ratio: f32,
+    count: u32,
+) -> impl Iterator<Item = (f32, f32)> {
+    dash_spans(starts_on, gaps, offset, extent, ratio, count)
+        .filter(move |_| dashed)
+        .chain(
+            (!dashed)
+                .then(|| cell_dashes(0.0, extent, ratio, count))
+                .into_iter()
+                .flatten(),
+        )
+}
+
+/// The `(start, end)` of each dash in a stitched cell from `start` to `end`, which is split
+/// into `count` equal parts that are each stitched for the first `ratio` of their length
+pub fn cell_dashes(
+    start: f32,
+    end: f32,
+    ratio: f32,
+    count: u32,
+) -> impl Iterator<Item = (f32, f32)> {
+    let part = (end - start) / count as f32;
+    (0..count).map(move |idx| {
+        let part_start = start + idx as f32 * part;
+        (part_start, part_start + part * ratio)
+    })
+}
+
+/// How many of `len` selectors to use for `lines` lines. That is all of them, unless the
+/// pattern is `tileable`: then it is the most that repeat a whole number of times across
+/// the lines, so the selectors start again from the first one on the next tile.
+fn selector_period(len: usize, lines: usize, tileable: bool) -> usize {
+    if !tileable || lines == 0 {
+        return len;
+    }
+    (1..=len.min(lines))
+        .rev()
+        .find(|period| lines.is_multiple_of(*period))
+        .unwrap_or(len)
+}
+
+/// The total length of thread, in millimeters, needed to sew every stitch of the pattern
+/// over a `width` x `height` pixel canvas printed at `px_per_mm` pixels per millimeter
+pub fn total_thread_length(
+    settings: &RectSettings,
+    width: u32,
+    height: u32,
+    px_per_mm: f32,
+) -> f32 {
+    Pattern::new(settings, width, height).thread_length() / px_per_mm
+}
+
+/// Set the pixel at (`x`, `y`) of an RGBA `buffer` to `color`. Pixels outside of the
+/// `width` x `height` image are ignored, so callers don't need to clip.
+fn set_pixel(buffer: &mut [u8], width: u32, height: u32, x: i64, y: i64, color: Rgb) {
+    blend_pixel(buffer, width, height, x, y, color, 1.0);
+}
+
+/// Blend `color` over the pixel at (`x`, `y`) of an RGBA `buffer`, where `coverage` of 1.0
+/// replaces the pixel entirely and 0.0 leaves it unchanged. Pixels outside of the
+/// `width` x `height` image are ignored.
+fn blend_pixel(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    x: i64,
+    y: i64,
+    color: Rgb,
+    coverage: f32,
+) {
+    if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+        return;
+    }
+    let idx = ((y as usize * width as usize) + x as usize) * 4;
+    let coverage = coverage.clamp(0.0, 1.0);
+    for (channel, value) in [color.red, color.green, color.blue].into_iter().enumerate() {
+        let old = buffer[idx + channel] as f32;
+        buffer[idx + channel] = (old + (value as f32 - old) * coverage).round() as u8;
+    }
+    buffer[idx + 3] = 255;
+}
+
+/// Write `text` in `color` into the `corner` of an RGBA `buffer`, using the bundled font at
+/// a height of `size` pixels. The text is inset from the edges by half its size.
+fn draw_caption(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    text: &str,
+    corner: Corner,
+    size: f32,
+    color: Rgb,
+) {
+    let (text_width, text_height) = measure_text(text, size);
+
+    // Work out where the top left of the text goes so that it sits in the chosen corner
+    let inset = size / 2.0;
+    let left = match corner {
+        Corner::TopLeft | Corner::BottomLeft => inset,
+        Corner::TopRight | Corner::BottomRight => width as f32 - inset - text_width,
+    };
+    let top = match corner {
+        Corner::TopLeft | Corner::TopRight => inset,
+        Corner::BottomLeft | Corner::BottomRight => height as f32 - inset - text_height,
+    };
+
+    draw_text(buffer, width, height, text, left, top, size, color);
+}
+
+/// Draw the legend strip into the rows of an RGBA `buffer` from `top` down to the bottom
+/// of the image: a thin rule, swatches of the stitch colors, and the seeds and spacing.
+fn draw_legend(buffer: &mut [u8], width: u32, height: u32, top: u32, settings: &RectSettings) {
+    let strip_height = (height - top) as f32;
+
+    // Separate the strip from the pattern with a thin gray rule
+    for x in 0..width {
+        set_pixel(
+            buffer,
+            width,
+            height,
+            x as i64,
+            top as i64,
+            Rgb::new(200, 200, 200),
+        );
+    }
+
+    // Swatches of the horizontal and vertical stitch colors side by side, centered
+    // vertically in the strip
+    let swatch = (strip_height / 2.0).round() as i64;
+    let inset = (strip_height - swatch as f32) / 2.0;
+    let swatch_left = inset.round() as i64;
+    let swatch_top = (top as f32 + inset).round() as i64;
+    for (idx, color) in [settings.horz_color, settings.vert_color]
+        .into_iter()
+        .enumerate()
+    {
+        let left = swatch_left + idx as i64 * swatch;
+        for y in swatch_top..swatch_top + swatch {
+            for x in left..left + swatch {
+                set_pixel(buffer, width, height, x, y, color);
+            }
+        }
+    }
+
+    let text = format!(
+        "horz seed {}  vert seed {}  spacing {}",
+        settings.horz_seed,
+        settings.vert_seed,
+        settings.spacing_label()
+    );
+    let size = strip_height * 0.4;
+    let (_, text_height) = measure_text(&text, size);
+    let text_left = (swatch_left + 2 * swatch) as f32 + inset;
+    let text_top = top as f32 + (strip_height - text_height) / 2.0;
+    draw_text(
+        buffer,
+        width,
+        height,
+        &text,
+        text_left,
+        text_top,
+        size,
+        settings.background_color.contrasting(),
+    );
+}
+
+/// The width and height in pixels of `text` on a single line in the bundled font at a
+/// height of `size` pixels
+fn measure_text(text: &str, size: f32) -> (f32, f32) {
+    let font = FontRef::try_from_slice(CAPTION_FONT).expect("The bundled font is invalid");
+    let font = font.as_scaled(PxScale::from(size));
+    let (_, width) = layout_text(&font, text, size);
+    (width, font.height())
+}
+
+/// Lay `text` out on a single line, with the top left of the line at the origin. Returns
+/// the positioned glyphs and the width of the line.
+fn layout_text<F: Font>(
+    font: &ab_glyph::PxScaleFont<F>,
+    text: &str,
+    size: f32,
+) -> (Vec<ab_glyph::Glyph>, f32) {
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += font.kern(previous, id);
+        }
+        glyphs.push(id.with_scale_and_position(size, point(caret, font.ascent())));
+        caret += font.h_advance(id);
+        previous = Some(id);
+    }
+    (glyphs, caret)
+}
+
+/// Write `text` in `color` into an RGBA `buffer` with its top left corner at (`left`,
+/// `top`), using the bundled font at a height of `size` pixels
+#[allow(clippy::too_many_arguments)]
+fn draw_text(
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    text: &str,
+    left: f32,
+    top: f32,
+    size: f32,
+    color: Rgb,
+) {
+    let font = FontRef::try_from_slice(CAPTION_FONT).expect("The bundled font is invalid");
+    let font = font.as_scaled(PxScale::from(size));
+    let (glyphs, _) = layout_text(&font, text, size);
+
+    for glyph in glyphs {
+        if let Some(outline) = font.outline_glyph(glyph) {
+            let glyph_bounds = outline.px_bounds();
+            outline.draw(|x, y, coverage| {
+                let px = (left + glyph_bounds.min.x).round() as i64 + x as i64;
+                let py = (top + glyph_bounds.min.y).round() as i64 + y as i64;
+                blend_pixel(buffer, width, height, px, py, color, coverage);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Cursor, time::Instant};
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    /// The RGBA pixel at (`x`, `y`) of a `width` pixels wide `buffer`
+    fn pixel(buffer: &[u8], width: u32, x: u32, y: u32) -> [u8; 4] {
+        let idx = (y as usize * width as usize + x as usize) * 4;
+        buffer[idx..idx + 4].try_into().unwrap()
+    }
+
+    /// Thin black lines 10 pixels apart, with selectors that start with
+    /// `[false, false, false, false, true, true]` horizontally and
+    /// `[false, false, true, true, false, false]` vertically
+    fn known_settings() -> RectSettings {
+        RectSettings::default()
+            .with_spacing(10.0)
+            .with_seeds(42, 7)
+            .with_line_weight(2.0)
+            .build()
+    }
+
+    #[test]
+    fn render_rgba_draws_known_seed() {
+        let settings = known_settings();
+        let buffer = render_rgba(40, 40, &settings);
+        assert_eq!(buffer.len(), 40 * 40 * 4);
+
+        // The top line's selector is false, so it starts with a gap and then a dash
+        assert_eq!(pixel(&buffer, 40, 5, 0), WHITE);
+        assert_eq!(pixel(&buffer, 40, 15, 0), BLACK);
+        assert_eq!(pixel(&buffer, 40, 25, 0), WHITE);
+        // And so does the left line, going down
+        assert_eq!(pixel(&buffer, 40, 0, 5), WHITE);
+        assert_eq!(pixel(&buffer, 40, 0, 15), BLACK);
+        assert_eq!(pixel(&buffer, 40, 0, 25), WHITE);
+        // The third vertical line's selector is true, so it starts with a dash
+        assert_eq!(pixel(&buffer, 40, 20, 5), BLACK);
+        assert_eq!(pixel(&buffer, 40, 20, 15), WHITE);
+    }
+
+    #[test]
+    fn render_rgba_fills_dashes_and_leaves_cells_blank() {
+        let settings = known_settings();
+        let buffer = render_rgba(40, 40, &settings);
+
+        for dash in Pattern::new(&settings, 40, 40).dashes() {
+            let middle = (dash.start + dash.end) / 2.0;
+            let (x, y) = match dash.axis {
+                Axis::Horizontal => (middle, dash.line_pos),
+                Axis::Vertical => (dash.line_pos, middle),
+            };
+            assert_eq!(pixel(&buffer, 40, x as u32, y as u32), BLACK);
+        }
+        // Nothing is drawn away from the lines
+        for y in (5..40).step_by(10) {
+            for x in (5..40).step_by(10) {
+                assert_eq!(pixel(&buffer, 40, x, y), WHITE);
+            }
+        }
+    }
+
+    #[test]
+    fn border_covers_outermost_pixels() {
+        let mut settings = known_settings();
+        settings.draw_border = true;
+        settings.border_color = Rgb::new(255, 0, 0);
+        settings.border_width = 3.0;
+        let (width, height) = (45, 32);
+        let buffer = pattern_pixels(width, height, &settings);
+
+        let red = [255, 0, 0, 255];
+        for x in 0..width {
+            for y in [0, 2, height - 3, height - 1] {
+                assert_eq!(pixel(&buffer, width, x, y), red, "({x}, {y})");
+            }
+        }
+        for y in 0..height {
+            for x in [0, 2, width - 3, width - 1] {
+                assert_eq!(pixel(&buffer, width, x, y), red, "({x}, {y})");
+            }
+        }
+        // The border is only as thick as its width
+        assert_ne!(pixel(&buffer, width, 3, 5), red);
+        assert_ne!(pixel(&buffer, width, 5, 3), red);
+
+        // Files saved before it was renamed still load their border's width
+        let loaded = RectSettings::from_json(r#"{"border_weight":3.0}"#).unwrap();
+        assert_eq!(loaded.border_width, 3.0);
+    }
+
+    #[test]
+    fn caption_is_drawn_in_its_corner() {
+        let mut settings = known_settings();
+        settings.draw_caption = true;
+        settings.caption_corner = Corner::TopLeft;
+        settings.caption_color = Rgb::new(255, 0, 0);
+        let (width, height) = (200, 100);
+        let plain = pattern_pixels(width, height, &settings);
+        let captioned = render_rgba(width, height, &settings);
+
+        // Only the pixels inside the caption's box, inset from the corner, change
+        let (text_width, text_height) = measure_text(&settings.caption(), settings.caption_size);
+        let inset = settings.caption_size / 2.0;
+        let mut changed = 0;
+        for y in 0..height {
+            for x in 0..width {
+                if pixel(&plain, width, x, y) != pixel(&captioned, width, x, y) {
+                    changed += 1;
+                    assert!((x as f32) < inset + text_width + 1.0, "({x}, {y})");
+                    assert!((y as f32) < inset + text_height + 1.0, "({x}, {y})");
+                }
+            }
+        }
+        assert!(changed > 0);
+        assert!(captioned
+            .chunks_exact(4)
+            .any(|pixel| pixel == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn disabled_caption_leaves_image_unchanged() {
+        let mut settings = known_settings();
+        settings.caption_text = "Not drawn".to_string();
+        assert_eq!(
+            render_rgba(200, 100, &settings),
+            pattern_pixels(200, 100, &settings)
+        );
+    }
+
+    #[test]
+    fn grid_lock_rounds_to_whole_cells() {
+        assert_eq!(GridLock::Off.apply(107, 25.0), 107);
+        assert_eq!(GridLock::Down.apply(107, 25.0), 100);
+        assert_eq!(GridLock::Up.apply(107, 25.0), 125);
+        // Sizes that are already whole cells are kept either way
+        assert_eq!(GridLock::Down.apply(100, 25.0), 100);
+        assert_eq!(GridLock::Up.apply(100, 25.0), 100);
+        // Fractional spacings round to the nearest pixel
+        assert_eq!(GridLock::Down.apply(100, 7.5), 98);
+        assert_eq!(GridLock::Up.apply(100, 7.5), 105);
+        // At least one cell is kept
+        assert_eq!(GridLock::Down.apply(10, 25.0), 25);
+    }
+
+    #[test]
+    fn export_size_follows_grid_lock() {
+        let mut settings = RectSettings::default().with_spacing(20.0).build();
+        settings.grid_lock = GridLock::Down;
+        assert_eq!(settings.export_size(215, 190), (200, 180));
+        settings.grid_lock = GridLock::Up;
+        assert_eq!(settings.export_size(215, 190), (220, 200));
+    }
+
+    #[test]
+    fn render_into_matches_render_rgba() {
+        let mut settings = known_settings();
+        settings.draw_caption = true;
+        settings.draw_legend = true;
+        let (width, height) = (120, 150);
+
+        // Start from leftovers of another render, which have to be cleared
+        let mut buffer = render_rgba(
+            width,
+            height,
+            &RectSettings::default().with_spacing(7.0).build(),
+        );
+        render_into(&mut buffer, width, height, &settings).unwrap();
+        assert_eq!(buffer, render_rgba(width, height, &settings));
+    }
+
+    #[test]
+    fn render_into_rejects_wrong_size() {
+        let mut buffer = vec![0; 10 * 10 * 4];
+        assert!(render_into(&mut buffer, 10, 11, &known_settings()).is_err());
+        // The buffer is left alone
+        assert!(buffer.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn reversing_twice_restores_selectors() {
+        let selectors = selectors_from_bits("1100101").unwrap();
+        let reversed = VertDerivation::Reverse.derive(&selectors);
+        assert_eq!(reversed, selectors_from_bits("1010011").unwrap());
+        assert_eq!(VertDerivation::Reverse.derive(&reversed), selectors);
+
+        // Rotating all the way around, in two steps, is the same as not rotating
+        let rotated = VertDerivation::Rotate(3).derive(&selectors);
+        assert_eq!(rotated, selectors_from_bits("0101110").unwrap());
+        assert_eq!(VertDerivation::Rotate(4).derive(&rotated), selectors);
+        assert!(VertDerivation::Rotate(3).derive(&[]).is_empty());
+    }
+
+    #[test]
+    fn derived_vert_selectors_follow_horz_selectors() {
+        let mut settings = known_settings();
+        settings.vert_derivation = VertDerivation::Reverse;
+        settings.regenerate_selectors();
+        let mut reversed = settings.horz_selectors.clone();
+        reversed.reverse();
+        assert_eq!(settings.vert_selectors, reversed);
+
+        // And keep following them when they are edited by hand
+        settings.toggle_selector(Axis::Horizontal, 0);
+        assert_eq!(
+            settings.vert_selectors.last(),
+            settings.horz_selectors.first()
+        );
+    }
+
+    #[test]
+    fn brick_staggers_every_other_line() {
+        let selectors = [true; 4];
+        let starts: Vec<_> = (0..6)
+            .map(|idx| brick_starts_on(&selectors, idx, true))
+            .collect();
+        assert_eq!(starts, [true, false, true, false, true, false]);
+        // Lines before line 0 keep alternating
+        assert!(!brick_starts_on(&selectors, -1, true));
+        assert!((0..6).all(|idx| brick_starts_on(&selectors, idx, false)));
+
+        // So with the same selector on every line, the first dashes swap between the first
+        // and second cells from one line to the next
+        let mut settings = RectSettings::default().with_spacing(10.0).build();
+        settings.brick = true;
+        settings.set_selectors(Axis::Horizontal, vec![false]);
+        let first_starts: Vec<_> = (0..4)
+            .map(|line| {
+                let y = line as f32 * 10.0;
+                Pattern::new(&settings, 50, 40)
+                    .dashes()
+                    .find(|dash| dash.axis == Axis::Horizontal && dash.line_pos == y)
+                    .unwrap()
+                    .start
+            })
+            .collect();
+        assert_eq!(first_starts, [10.0, 0.0, 10.0, 0.0]);
+    }
+
+    #[test]
+    fn thread_length_of_small_pattern() {
+        // Two lines each way, 10 pixels apart. With every selector true each line starts with
+        // a 10 pixel dash, and the next one would end past the edge, so there are 4 dashes
+        let mut settings = RectSettings::default().with_spacing(10.0).build();
+        settings.set_selectors(Axis::Horizontal, vec![true]);
+        settings.set_selectors(Axis::Vertical, vec![true]);
+        assert_eq!(Pattern::new(&settings, 20, 20).thread_length(), 40.0);
+        assert_eq!(total_thread_length(&settings, 20, 20, 2.0), 20.0);
+
+        // Splitting each cell into two dashes that each cover half of their part halves the
+        // thread
+        settings.dashes_per_cell = 2;
+        settings.dash_ratio = 0.5;
+        assert_eq!(total_thread_length(&settings, 20, 20, 2.0), 10.0);
+    }
+
+    #[test]
+    fn file_name_fills_in_template() {
+        let mut settings = RectSettings::default()
+            .with_spacing(25.0)
+            .with_seeds(3, 14)
+            .build();
+        assert_eq!(settings.file_name(), "hito_h3_v14_s25.png");
+
+        settings.horz_spacing = 10.0;
+        settings.vert_spacing = 7.5;
+        settings.file_name_template = "{spacing}-{vert_seed}-{horz_seed}.png".to_string();
+        assert_eq!(settings.file_name(), "10x7.5-14-3.png");
+
+        // Characters that can't go in file names are replaced, but placeholders next to
+        // them are still filled in and unknown ones are kept
+        settings.file_name_template = "a/b\\c:d*e?f\"g<h>i|j\t{horz_seed}{seed}.png".to_string();
+        assert_eq!(settings.file_name(), "a_b_c_d_e_f_g_h_i_j_3{seed}.png");
+        settings.file_name_template = "ünïcode {horz_seed}.png".to_string();
+        assert_eq!(settings.file_name(), "ünïcode 3.png");
+    }
+
+    #[test]
+    fn toggling_twice_restores_selector() {
+        let mut settings = known_settings();
+        let original = settings.vert_selectors[2];
+        settings.dirty = false;
+
+        assert_eq!(settings.toggle_selector(Axis::Vertical, 2), !original);
+        assert!(settings.dirty);
+        assert!(settings.edited);
+
+        settings.dirty = false;
+        assert_eq!(settings.toggle_selector(Axis::Vertical, 2), original);
+        assert!(settings.dirty);
+        assert_eq!(settings.vert_selectors, known_settings().vert_selectors);
+    }
+
+    #[test]
+    fn svg_settings_round_trip() {
+        let mut settings = known_settings();
+        // Characters that have to be escaped in XML
+        settings.caption_text = "<b>Tom & Jerry</b> &lt;".to_string();
+        settings.toggle_selector(Axis::Horizontal, 1);
+
+        let mut svg = Vec::new();
+        write_svg_to(&mut svg, &settings, 40, 30).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(!svg.contains("<b>"));
+        let loaded = settings_from_svg(&svg).unwrap();
+        assert_eq!(loaded.to_json(), settings.to_json());
+        assert!(loaded.edited);
+
+        assert!(matches!(
+            settings_from_svg("<svg></svg>"),
+            Err(LoadError::MissingSettings)
+        ));
+    }
+
+    #[test]
+    fn legend_adds_strip_with_text() {
+        let mut settings = known_settings();
+        settings.draw_legend = true;
+        let (width, height) = settings.export_size(400, 80);
+        assert_eq!((width, height), (400, 80 + LEGEND_HEIGHT));
+        assert_eq!(settings.legend_height(), LEGEND_HEIGHT);
+
+        // The pattern is drawn above the strip as if there were no strip
+        let buffer = render_rgba(width, height, &settings);
+        let pattern_len = 400 * 80 * 4;
+        assert_eq!(buffer[..pattern_len], pattern_pixels(400, 80, &settings));
+
+        // The text starts after the two swatches, which are each half the strip's height
+        // and inset by a quarter of it. The gray rule along the top of the strip is skipped.
+        let text_left = LEGEND_HEIGHT / 4 + LEGEND_HEIGHT + LEGEND_HEIGHT / 4;
+        let text_pixels = (81..height)
+            .flat_map(|y| (text_left..width).map(move |x| (x, y)))
+            .filter(|&(x, y)| pixel(&buffer, width, x, y) != WHITE)
+            .count();
+        assert!(text_pixels > 100, "{text_pixels} text pixels");
+
+        settings.draw_legend = false;
+        assert_eq!(settings.export_size(400, 80), (400, 80));
+    }
+
+    /// The density of the `len` selectors `seed` generates with `bias`
+    fn seed_density(seed: u64, len: usize, bias: f64) -> f32 {
+        let mut selectors = vec![false; len];
+        fill_selectors(&mut selectors, seed, bias);
+        selector_density(&selectors)
+    }
+
+    #[test]
+    fn density_band_seed_is_inside_band() {
+        let mut rng: Pcg64 = Seeder::from(1).make_rng();
+        for _ in 0..20 {
+            let seed = seed_in_density_band(&mut rng, 20, 0.5, 0.4, 0.6);
+            assert!((0.4..=0.6).contains(&seed_density(seed, 20, 0.5)), "{seed}");
+        }
+        // Even against the bias
+        let seed = seed_in_density_band(&mut rng, 20, 0.2, 0.3, 0.5);
+        assert!((0.3..=0.5).contains(&seed_density(seed, 20, 0.2)), "{seed}");
+    }
+
+    #[test]
+    fn impossible_density_band_gives_closest_seed() {
+        // No selectors can be more than all true, so this tries every seed it is allowed
+        // to and then settles for the densest
+        let mut rng: Pcg64 = Seeder::from(2).make_rng();
+        let tried: Vec<u64> = {
+            let mut rng = rng.clone();
+            (0..MAX_DENSITY_TRIES).map(|_| rng.gen()).collect()
+        };
+        let seed = seed_in_density_band(&mut rng, 20, 0.5, 1.5, 2.0);
+        let densest = tried
+            .iter()
+            .map(|&seed| seed_density(seed, 20, 0.5))
+            .fold(0.0, f32::max);
+        assert!(tried.contains(&seed));
+        assert_eq!(seed_density(seed, 20, 0.5), densest);
+    }
+
+    #[test]
+    fn png_settings_round_trip() {
+        let mut settings = RectSettings::default()
+            .with_spacing(12.5)
+            .with_seeds(300, 9)
+            .build();
+        settings.horz_spacing = 14.0;
+        settings.toggle_selector(Axis::Vertical, 4);
+
+        let mut png = Vec::new();
+        write_png_to(&mut png, &settings, 60, 40).unwrap();
+        let json = read_settings_chunk_from(Cursor::new(png)).unwrap().unwrap();
+        let loaded = RectSettings::from_json(&json).unwrap();
+        // Everything that is saved comes back, including the edited selectors
+        assert_eq!(loaded.to_json(), settings.to_json());
+        assert!(loaded.edited);
+
+        // Each layer of a layered PNG comes back too, from its Layers chunk
+        let mut top = RectSettings::default().with_seeds(5, 6).build();
+        top.horz_color = Rgb::new(255, 0, 0);
+        let layers = [settings, top];
+        let mut png = Vec::new();
+        write_layers_png_to(&mut png, &layers, 60, 40).unwrap();
+        let json = read_text_chunk(Cursor::new(png), "Layers")
+            .unwrap()
+            .unwrap();
+        let values: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        let loaded: Vec<_> = values
+            .into_iter()
+            .map(|value| RectSettings::from_value(value).unwrap().to_json())
+            .collect();
+        assert_eq!(loaded, layers.map(|layer| layer.to_json()));
+
+        // Nor can a PNG be written without any layers
+        let e = write_layers_png_to(Vec::new(), &[], 60, 40).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(LoadError::BadField("layers"))
+        ));
+    }
+
+    #[test]
+    fn hito_file_round_trip() {
+        let settings = RectSettings::default()
+            .with_spacing(8.0)
+            .with_seeds(5, 6)
+            .build();
+        let loaded = HitoFile::from_json(&HitoFile::new(settings.clone()).to_json()).unwrap();
+        assert_eq!(loaded.version, HITO_VERSION);
+        assert_eq!(loaded.settings.to_json(), settings.to_json());
+        assert!(!loaded.settings.edited);
+    }
+
+    #[test]
+    fn png_without_settings_has_no_chunk() {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 1, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&WHITE)
+            .unwrap();
+        assert!(read_settings_chunk_from(Cursor::new(png))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn tileable_pattern_continues_across_edges() {
+        let mut settings = RectSettings::default().with_spacing(10.0).with_seeds(3, 4);
+        settings.tileable = true;
+        let (width, height) = settings.pattern_size(105, 83);
+        assert_eq!((width, height), (100, 80));
+        settings.fit_selectors(width, height);
+        let settings = settings.build();
+        let tile = pattern_pixels(width, height, &settings);
+
+        // Drawing the pattern over two tiles each way gives the same tile four times, so
+        // the column after the right edge of a tile is its leftmost column again, and the
+        // row after its bottom edge is its top row
+        let tiled = pattern_pixels(2 * width, 2 * height, &settings);
+        for y in 0..2 * height {
+            for x in 0..2 * width {
+                assert_eq!(
+                    pixel(&tiled, 2 * width, x, y),
+                    pixel(&tile, width, x % width, y % height),
+                    "({x}, {y})"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn half_pixel_spacing_lines_meet_on_grid() {
+        // Every cell stitched, so the lines form a solid grid. The lines are a pixel thick
+        // and 7.5 pixels apart, so each one fills the pixel whose center is within half a
+        // pixel of it, sometimes just after it and sometimes just before
+        let mut settings = RectSettings::default()
+            .with_spacing(7.5)
+            .with_line_weight(1.0)
+            .build();
+        settings.dashed = false;
+        let buffer = pattern_pixels(40, 40, &settings);
+        let lines = [7, 14, 22, 29, 37];
+        for y in 0..40 {
+            for x in 0..40 {
+                let on_line = lines.contains(&x) || lines.contains(&y);
+                let expected = if on_line { BLACK } else { WHITE };
+                assert_eq!(pixel(&buffer, 40, x, y), expected, "({x}, {y})");
+            }
+        }
+
+        // A dash from one grid point to the next covers the rows from the row of the line it
+        // starts on to the row of the line it ends on, so it meets both of them
+        let mut settings = RectSettings::default()
+            .with_spacing(7.5)
+            .with_line_weight(1.0)
+            .build();
+        settings.set_selectors(Axis::Vertical, vec![false]);
+        let buffer = pattern_pixels(40, 40, &settings);
+        let column: Vec<_> = (0..40).map(|y| pixel(&buffer, 40, 7, y) == BLACK).collect();
+        assert_eq!(
+            column[6..16],
+            [false, true, true, true, true, true, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn all_true_selectors_start_every_line_with_dash() {
+        let mut settings = RectSettings::default().with_spacing(10.0).build();
+        settings.set_selectors(Axis::Horizontal, vec![true; 4]);
+        settings.set_selectors(Axis::Vertical, vec![true; 4]);
+        let buffer = pattern_pixels(50, 50, &settings);
+
+        // The top line is a dash over every other cell from the left edge, 0..10, 20..30 and
+        // 40..50, which ends on the right edge. Elsewhere only the vertical lines reach the
+        // top row, each 3 pixels thick and starting with a dash too.
+        let top_row: String = (0..50)
+            .map(|x| match pixel(&buffer, 50, x, 0) {
+                BLACK => '#',
+                WHITE => '.',
+                _ => '?',
+            })
+            .collect();
+        assert_eq!(
+            top_row,
+            "###########.......#############.......############"
+        );
+
+        // Each top dash is where the live view draws it, from one vertical line to the next
+        let top_dashes: Vec<_> = Pattern::new(&settings, 50, 50)
+            .dashes()
+            .filter(|dash| dash.axis == Axis::Horizontal && dash.line_pos == 0.0)
+            .map(|dash| (dash.start, dash.end))
+            .collect();
+        assert_eq!(top_dashes, [(0.0, 10.0), (20.0, 30.0), (40.0, 50.0)]);
+    }
+
+    #[test]
+    fn loops_are_counted() {
+        let with_selectors = |horz: &[bool], vert: &[bool]| {
+            let mut settings = known_settings();
+            settings.set_selectors(Axis::Horizontal, horz.to_vec());
+            settings.set_selectors(Axis::Vertical, vert.to_vec());
+            settings
+        };
+
+        // Every line starts with a gap, so over 4 x 4 cells the stitches around cells (1, 1),
+        // (3, 1), (1, 3) and (3, 3) close into squares. Cut off by the top and left edges
+        // are the straight pieces of the squares that would be above and left of them.
+        let all_false = with_selectors(&[false], &[false]);
+        assert_eq!(count_loops(&all_false, 4, 4), 8);
+        // Over 3 x 3 cells there is one square, and all four edges cut off a piece
+        assert_eq!(count_loops(&all_false, 3, 3), 5);
+        // Same again, a cell over, when every line starts with a dash
+        let all_true = with_selectors(&[true], &[true]);
+        assert_eq!(count_loops(&all_true, 4, 4), 8);
+
+        // Alternating starts on both axes give staircases running down to the left, which
+        // all reach the edges: three from the top edge and one from the right edge
+        let alternating = with_selectors(&[true, false], &[true, false]);
+        assert_eq!(count_loops(&alternating, 4, 4), 4);
+
+        // Alternating horizontal lines over all-false vertical ones zigzag across rows 1 to 2
+        // and rows 3 to 4, from the left edge to the right one. Above them, the top line's
+        // two dashes are pieces of their own.
+        let mixed = with_selectors(&[true, false], &[false]);
+        assert_eq!(count_loops(&mixed, 4, 4), 4);
+        let (pieces, _) = stitch_loops(&mixed, 4, 4);
+        let point = |col: usize, row: usize| pieces[row * 5 + col];
+        assert_eq!(point(0, 1), point(4, 2));
+        assert_eq!(point(0, 3), point(4, 4));
+        assert_ne!(point(0, 1), point(0, 3));
+        assert_ne!(point(0, 0), point(2, 0));
+        assert_ne!(point(1, 0), point(1, 1));
+        // Points without stitches, like the top right corner, are in none
+        assert_eq!(point(4, 0), None);
+
+        // No cells have no stitches
+        assert_eq!(count_loops(&all_false, 0, 0), 0);
+    }
+
+    #[test]
+    fn lines_are_counted_in_part_of_the_image() {
+        let settings = known_settings();
+        let pattern = Pattern::new(&settings, 40, 40);
+        assert_eq!(pattern.margin(), 0.0);
+        // All of the image has all of the lines
+        for axis in [Axis::Horizontal, Axis::Vertical] {
+            let all: Vec<i64> = pattern.line_numbers(axis).collect();
+            assert_eq!(
+                pattern.line_numbers_in(axis, [[0.0, 0.0], [40.0, 40.0]]),
+                all
+            );
+        }
+        // A strip down the middle has only the vertical lines at 20 and 30, though it crosses
+        // every horizontal one
+        let strip = [[15.0, 0.0], [30.0, 40.0]];
+        assert_eq!(pattern.line_numbers_in(Axis::Vertical, strip), [2, 3]);
+        assert_eq!(
+            pattern.line_numbers_in(Axis::Horizontal, strip),
+            pattern.line_numbers(Axis::Horizontal).collect::<Vec<_>>()
+        );
+
+        // On a polar grid with two rings and twelve spokes, the top left corner is crossed by
+        // the outer ring and the two spokes pointing up and left from the center, but not by
+        // the first ring or any other spoke
+        let mut polar = known_settings();
+        polar.grid = Grid::Polar;
+        let pattern = Pattern::new(&polar, 40, 40);
+        let area = [[0.0, 0.0], [12.0, 12.0]];
+        assert_eq!(pattern.line_numbers_in(Axis::Horizontal, area), [2]);
+        assert_eq!(pattern.line_numbers_in(Axis::Vertical, area), [7, 8]);
+    }
+
+    /// Settings whose 40 x 40 pattern tiles, so its dashes reach the lines at the far edges
+    /// and cover the same 4 x 4 cells as `count_loops` does, with a mix of closed loops and
+    /// pieces cut off by the edges
+    fn loop_settings() -> RectSettings {
+        let mut settings = known_settings();
+        settings.tileable = true;
+        settings.set_selectors(Axis::Horizontal, vec![true, false, false, true]);
+        settings.set_selectors(Axis::Vertical, vec![false, true, false, false]);
+        settings
+    }
+
+    #[test]
+    fn dash_loops_match_stitch_loops() {
+        let settings = loop_settings();
+        let pattern = Pattern::new(&settings, 40, 40);
+        let (loops, count) = pattern.dash_loops();
+        assert_eq!(count, count_loops(&settings, 4, 4));
+        assert_eq!(loops.iter().max(), Some(&(count - 1)));
+
+        // Each dash is in the loop of the piece its first grid point is in, and the two are
+        // numbered the same way, so the numbers match one for one
+        let (pieces, _) = stitch_loops(&settings, 4, 4);
+        let dashes: Vec<_> = pattern.dashes().collect();
+        assert_eq!(dashes.len(), loops.len());
+        let mut piece_of_loop = HashMap::new();
+        for (dash, &dash_loop) in dashes.iter().zip(&loops) {
+            let (col, row) = match dash.axis {
+                Axis::Vertical => (dash.line_pos, dash.start.min(dash.end)),
+                Axis::Horizontal => (dash.start.min(dash.end), dash.line_pos),
+            };
+            let piece = pieces[(row / 10.0) as usize * 5 + (col / 10.0) as usize].unwrap();
+            assert_eq!(*piece_of_loop.entry(dash_loop).or_insert(piece), piece);
+        }
+        let mut matched: Vec<_> = piece_of_loop.into_values().collect();
+        matched.sort_unstable();
+        matched.dedup();
+        assert_eq!(matched.len(), count);
+    }
+
+    #[test]
+    fn loops_get_their_own_colors() {
+        let mut settings = loop_settings();
+        settings.color_by_loop = true;
+        let pattern = Pattern::new(&settings, 40, 40);
+        let (loops, count) = pattern.dash_loops();
+        // Few enough loops that the palette doesn't come round again
+        assert!(count > 1 && count <= LOOP_PALETTE.len());
+
+        let styled = pattern.styled_dashes();
+        let buffer = render_rgba(40, 40, &settings);
+        for ((dash, style), &dash_loop) in styled.iter().zip(&loops) {
+            assert_eq!(style.color, LOOP_PALETTE[dash_loop]);
+            // The middle of each dash is drawn in its loop's color too
+            let middle = ((dash.start + dash.end) / 2.0) as u32;
+            let line = dash.line_pos as u32;
+            let (x, y) = match dash.axis {
+                Axis::Vertical => (line, middle),
+                Axis::Horizontal => (middle, line),
+            };
+            let Rgb { red, green, blue } = LOOP_PALETTE[dash_loop];
+            assert_eq!(
+                pixel(&buffer, 40, x.min(39), y.min(39)),
+                [red, green, blue, 255]
+            );
+        }
+        // So dashes share a color exactly when they share a loop
+        for (a, (_, a_style)) in styled.iter().enumerate() {
+            for (b, (_, b_style)) in styled.iter().enumerate() {
+                assert_eq!(a_style.color == b_style.color, loops[a] == loops[b]);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_selectors_dont_panic() {
+        for grid in Grid::ALL {
+            let mut settings = RectSettings::default()
+                .with_spacing(10.0)
+                .with_grid(grid)
+                .build();
+            settings.tileable = grid == Grid::Square;
+            settings.set_selectors(Axis::Horizontal, Vec::new());
+            settings.set_selectors(Axis::Vertical, Vec::new());
+            settings.diag_selectors.clear();
+
+            // No selectors are the same as false ones, so there are still stitches
+            let buffer = render_rgba(40, 40, &settings);
+            assert!(buffer.chunks_exact(4).any(|pixel| pixel == BLACK));
+            write_png_to(Vec::new(), &settings, 40, 40).unwrap();
+            write_svg_to(Vec::new(), &settings, 40, 40).unwrap();
+            pattern_to_ascii(&settings, 4, 4);
+            pattern_to_csv(&settings, 4, 4);
+            count_loops(&settings, 4, 4);
+            Pattern::new(&settings, 40, 40).thread_length();
+        }
+
+        // Files saved with empty selectors are rejected rather than loaded
+        let loaded = RectSettings::from_json(r#"{"horz_selectors":[],"vert_selectors":[]}"#);
+        assert!(matches!(loaded, Err(LoadError::BadField("horz_selectors"))));
+    }
+
+    #[test]
+    fn seeds_give_fixed_selectors() {
+        // These must never change, or every saved pattern would change with them. Seed 1000
+        // doesn't fit in a u8, so it is hashed the other way.
+        let cases = [
+            (0, 0.5, "011101110000001101110010"),
+            (42, 0.5, "000011101010000110111100"),
+            (1000, 0.5, "100101100111100011111100"),
+            (42, 0.25, "001000000100001001000101"),
+        ];
+        for (seed, bias, bits) in cases {
+            let mut selectors = vec![false; 24];
+            fill_selectors(&mut selectors, seed, bias);
+            assert_eq!(
+                selectors_to_bits(&selectors),
+                bits,
+                "seed {seed}, bias {bias}"
+            );
+        }
+    }
+
+    #[test]
+    fn longer_selector_runs_start_with_shorter_ones() {
+        for (seed, bias) in [(0, 0.5), (1000, 0.5), (42, 0.25)] {
+            let mut short = [false; 5];
+            let mut long = [false; 20];
+            fill_selectors(&mut short, seed, bias);
+            fill_selectors(&mut long, seed, bias);
+            assert_eq!(long[..5], short, "seed {seed}, bias {bias}");
+        }
+
+        // So growing the window only adds lines at the far edges
+        let mut settings = known_settings();
+        settings.fit_selectors(50, 30);
+        let (horz, vert) = (
+            settings.horz_selectors.clone(),
+            settings.vert_selectors.clone(),
+        );
+        settings.fit_selectors(400, 300);
+        assert!(settings.horz_selectors.len() > horz.len());
+        assert!(settings.vert_selectors.len() > vert.len());
+        assert!(settings.horz_selectors.starts_with(&horz));
+        assert!(settings.vert_selectors.starts_with(&vert));
+    }
+
+    #[test]
+    fn biases_in_same_step_give_same_selectors() {
+        // 0.3 is between steps 76 and 77 of 256, and rounds up to 77 like 0.3005 does
+        let (bias, nearby) = (0.3, 0.3005);
+        assert_eq!(quantize_bias(bias), 77.0 / BIAS_STEPS as f64);
+        assert_eq!(quantize_bias(nearby), quantize_bias(bias));
+        let mut selectors = vec![false; 200];
+        let mut nearby_selectors = vec![false; 200];
+        fill_selectors(&mut selectors, 42, bias);
+        fill_selectors(&mut nearby_selectors, 42, nearby);
+        assert_eq!(selectors, nearby_selectors);
+
+        // A whole step away is a different bias, with different selectors
+        let mut next_step = vec![false; 200];
+        fill_selectors(&mut next_step, 42, bias + 1.0 / BIAS_STEPS as f64);
+        assert_ne!(selectors, next_step);
+    }
+
+    /// Draw `settings` the way `draw_pattern_to_image` did before it drew bands of rows in
+    /// parallel: every stitch in turn over the whole image, then the border
+    fn draw_pattern_serially(buffer: &mut [u8], width: u32, height: u32, settings: &RectSettings) {
+        let pattern = Pattern::new(settings, width, height);
+        let rows = 0..height as i64;
+        match settings.grid {
+            Grid::Square => {
+                for (dash, style) in pattern.styled_dashes() {
+                    draw_dash_to_image(buffer, width, height, rows.clone(), &dash, style);
+                }
+            }
+            Grid::Triangular | Grid::Polar => {
+                for stitch in pattern.stitches() {
+                    let style = settings.line_style(stitch.axis);
+                    draw_stitch_to_image(buffer, width, height, rows.clone(), &stitch, style);
+                }
+            }
+        }
+        if settings.draw_border {
+            draw_border_to_image(buffer, width, height, rows, settings);
+        }
+    }
+
+    /// Patterns whose stitches cross the edges of the bands in every way they can: thick
+    /// and blended, see-through where they overlap, at an angle, and with a border
+    fn banding_settings() -> Vec<RectSettings> {
+        let base = RectSettings::default()
+            .with_spacing(13.0)
+            .with_seeds(11, 12)
+            .with_line_weight(7.5);
+        let mut all = vec![base.clone().build()];
+        for grid in Grid::ALL {
+            let mut settings = base.clone().with_grid(grid);
+            settings.antialias = true;
+            settings.round_caps = true;
+            settings.opacity = 0.6;
+            settings.gradient = true;
+            settings.draw_border = true;
+            settings.offset_y = 5.5;
+            all.push(settings.build());
+        }
+        all
+    }
+
+    #[test]
+    fn parallel_drawing_matches_serial() {
+        // Heights that end part way through a band, and exactly on the edge of one
+        for (width, height) in [(97, 201), (64, BAND_ROWS * 3), (10, 5)] {
+            for settings in banding_settings() {
+                let mut parallel = background_buffer(width, height, Rgb::WHITE);
+                let mut serial = parallel.clone();
+                draw_pattern_to_image(&mut parallel, width, height, &settings);
+                draw_pattern_serially(&mut serial, width, height, &settings);
+                assert!(
+                    parallel == serial,
+                    "{:?} at {width}x{height}",
+                    settings.grid
+                );
+            }
+        }
+    }
+
+    /// How much faster drawing in parallel is, on a 4K image. Run it with
+    /// `cargo test --release -- --ignored --nocapture parallel_drawing_speed`.
+    #[test]
+    #[ignore]
+    fn parallel_drawing_speed() {
+        let (width, height) = (3840, 2160);
+        for settings in banding_settings() {
+            let mut buffer = background_buffer(width, height, Rgb::WHITE);
+            let start = Instant::now();
+            draw_pattern_serially(&mut buffer, width, height, &settings);
+            let serial = start.elapsed();
+            let start = Instant::now();
+            draw_pattern_to_image(&mut buffer, width, height, &settings);
+            let parallel = start.elapsed();
+            println!(
+                "{:?}: serial {serial:?}, parallel {parallel:?}",
+                settings.grid
+            );
+        }
+    }
+
+    #[test]
+    fn png_records_dpi() {
+        let mut settings = known_settings();
+        for (dpi, pixels_per_meter) in [(96, 3780), (300, 11811), (1, 39)] {
+            settings.dpi = dpi;
+            let mut png = Vec::new();
+            write_png_to(&mut png, &settings, 20, 10).unwrap();
+
+            let reader = png::Decoder::new(Cursor::new(png)).read_info().unwrap();
+            let dims = reader.info().pixel_dims.expect("The PNG has a pHYs chunk");
+            assert_eq!((dims.xppu, dims.yppu), (pixels_per_meter, pixels_per_meter));
+            assert_eq!(dims.unit, png::Unit::Meter);
+        }
+    }
+
+    #[test]
+    fn low_contrast_colors_are_flagged() {
+        assert_eq!(Rgb::BLACK.contrast_ratio(Rgb::WHITE), 21.0);
+        assert_eq!(Rgb::WHITE.contrast_ratio(Rgb::BLACK), 21.0);
+
+        // Black on white stands out however it is seen
+        let mut settings = known_settings();
+        for filter in ContrastFilter::ALL {
+            assert!(
+                settings.low_contrast_colors(filter).is_empty(),
+                "{filter:?}"
+            );
+        }
+
+        // Yellow on white is hard to see even as it is
+        let yellow = Rgb::new(255, 255, 0);
+        settings.horz_color = yellow;
+        assert_eq!(settings.low_contrast_colors(ContrastFilter::Off), [yellow]);
+
+        // Dark gray is fine on white, until the contrast is reduced
+        let dark_gray = Rgb::new(90, 90, 90);
+        settings.horz_color = dark_gray;
+        assert!(settings.low_contrast_colors(ContrastFilter::Off).is_empty());
+        assert_eq!(
+            settings.low_contrast_colors(ContrastFilter::Reduced),
+            [dark_gray]
+        );
+
+        // Seen in gray, colors are as bright as they look
+        let mut buffer = vec![255, 0, 0, 255, 0, 0, 255, 128];
+        ContrastFilter::Grayscale.apply_rgba(&mut buffer);
+        assert_eq!(buffer, [76, 76, 76, 255, 29, 29, 29, 128]);
+    }
+
+    #[test]
+    fn query_round_trip() {
+        let mut settings = RectSettings::default()
+            .with_spacing(12.5)
+            .with_seeds(300, 9)
+            // A whole number of steps, since the biases are saved as steps
+            .with_bias(0.25)
+            .with_line_weight(3.5)
+            .build();
+        settings.vert_spacing = 14.0;
+        settings.spacing_alt = Some(4.5);
+        settings.brick = true;
+        settings.offset_x = 3.25;
+        settings.dash_ratio = 0.6;
+        settings.horz_color = Rgb::new(200, 30, 0);
+        settings.background_color = Rgb::new(250, 240, 220);
+        settings.draw_caption = true;
+        settings.caption_text = "Seeds & spacing: 100%".to_string();
+        settings.regenerate_selectors();
+        settings.toggle_selector(Axis::Vertical, 4);
+
+        let query = settings_to_query(&settings);
+        assert!(query.starts_with("horz_spacing=12.5&vert_spacing=14&horz_seed=300"));
+        assert!(query.contains("&brick=true&"), "{query}");
+        assert!(query.contains("&caption_text=Seeds%20%26%20spacing%3A%20100%25&"));
+        // Only the settings that differ from the defaults are given
+        assert!(!query.contains("vert_color"), "{query}");
+        assert_eq!(settings_from_query(&query).unwrap(), settings);
+
+        // The defaults only need their spacing and seeds
+        let defaults = RectSettings::default();
+        let query = settings_to_query(&defaults);
+        assert_eq!(query, "spacing=25&horz_seed=0&vert_seed=0");
+        assert_eq!(settings_from_query(&query).unwrap(), defaults);
+
+        for (query, key) in [
+            ("brick=maybe", "brick"),
+            ("opacity=2", "opacity"),
+            ("caption_text=%zz", "caption_text"),
+            ("spacing=0", "spacing"),
+        ] {
+            let e = settings_from_query(query).unwrap_err();
+            assert!(
+                matches!(e, QueryError::Invalid { key: bad, .. } if bad == key),
+                "{query}"
+            );
+        }
+        assert!(matches!(
+            settings_from_query("edited=true"),
+            Err(QueryError::Unknown(_))
+        ));
+    }
+
+    #[test]
+    fn zero_spacing_is_clamped() {
+        let loaded = RectSettings::from_json(r#"{"horz_spacing":0.0,"vert_spacing":-5.0}"#);
+        let loaded = loaded.unwrap();
+        assert_eq!(
+            (loaded.horz_spacing, loaded.vert_spacing),
+            (MIN_SPACING, MIN_SPACING)
+        );
+        // And in older saves with a single spacing
+        let loaded = RectSettings::from_json(r#"{"spacing":0.0,"spacing_alt":0.0}"#).unwrap();
+        assert_eq!(
+            (loaded.horz_spacing, loaded.vert_spacing),
+            (MIN_SPACING, MIN_SPACING)
+        );
+        assert_eq!(loaded.spacing_alt, Some(MIN_SPACING));
+
+        // Spacings set to 0 directly are drawn at the minimum rather than hanging
+        let mut settings = RectSettings::default().with_spacing(0.0).build();
+        settings.spacing_alt = Some(0.0);
+        assert_eq!(settings.spacing(Axis::Horizontal), MIN_SPACING);
+        assert_eq!(
+            settings.line_gaps(Axis::Vertical),
+            (MIN_SPACING, MIN_SPACING)
+        );
+        for grid in Grid::ALL {
+            settings.grid = grid;
+            render_rgba(20, 20, &settings);
+        }
+    }
+
+    /// A 1x1 PNG with `chunk` before its image data
+    fn png_with_chunk(chunk: &impl png::text_metadata::EncodableTextChunk) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 1, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_text_chunk(chunk).unwrap();
+        writer.write_image_data(&WHITE).unwrap();
+        drop(writer);
+        png
+    }
+
+    #[test]
+    fn reads_compressed_settings_chunks() {
+        use png::text_metadata::{ITXtChunk, ZTXtChunk};
+        let json = known_settings().to_json();
+
+        let ztxt = png_with_chunk(&ZTXtChunk::new("Settings", &json));
+        let read = read_settings_chunk_from(Cursor::new(ztxt)).unwrap();
+        assert_eq!(read.as_deref(), Some(json.as_str()));
+
+        let mut itxt = ITXtChunk::new("Settings", &json);
+        itxt.compress_text().unwrap();
+        let read = read_settings_chunk_from(Cursor::new(png_with_chunk(&itxt))).unwrap();
+        assert_eq!(read.as_deref(), Some(json.as_str()));
+
+        // Chunks with other keywords are left alone
+        let other = png_with_chunk(&ZTXtChunk::new("Comment", &json));
+        assert!(read_settings_chunk_from(Cursor::new(other))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn antialias_blends_edges_gray() {
+        // Lines 2.5 pixels thick, so each covers a quarter of the rows (or columns) on either
+        // side of the two it fills
+        let mut settings = RectSettings::default()
+            .with_spacing(10.0)
+            .with_line_weight(2.5)
+            .build();
+        settings.set_selectors(Axis::Horizontal, vec![true]);
+        settings.set_selectors(Axis::Vertical, vec![true]);
+        settings.offset_x = 5.0;
+        let is_gray = |pixel: [u8; 4]| pixel != BLACK && pixel != WHITE;
+
+        let hard = pattern_pixels(30, 30, &settings);
+        assert!(!hard
+            .chunks_exact(4)
+            .any(|pixel| is_gray(pixel.try_into().unwrap())));
+
+        settings.antialias = true;
+        let smooth = pattern_pixels(30, 30, &settings);
+        // A crop across the middle of the dash from 5 to 15 along the line at y = 20
+        let crop: Vec<_> = (17..23).map(|y| pixel(&smooth, 30, 10, y)).collect();
+        let quarter = [191, 191, 191, 255];
+        assert_eq!(crop, [WHITE, quarter, BLACK, BLACK, quarter, WHITE]);
+    }
+
+    #[test]
+    fn dashes_never_run_past_their_lines() {
+        let (width, height) = (97, 61);
+        for spacing in [7.0, 7.5, 10.0, 13.3, 25.0] {
+            for (dash_ratio, dashes_per_cell) in [(1.0, 1), (0.6, 1), (0.5, 3)] {
+                for spacing_alt in [None, Some(4.5)] {
+                    let mut settings = RectSettings::default()
+                        .with_spacing(spacing)
+                        .with_seeds(5, 9);
+                    settings.dash_ratio = dash_ratio;
+                    settings.dashes_per_cell = dashes_per_cell;
+                    settings.spacing_alt = spacing_alt;
+                    settings.offset_x = 3.25;
+                    let settings = settings.build();
+
+                    let mut line_lengths: HashMap<(bool, u32), f32> = HashMap::new();
+                    let (mut cut_at_end, mut cut_at_start) = ([false; 2], false);
+                    for dash in Pattern::new(&settings, width, height).dashes() {
+                        let extent = match dash.axis {
+                            Axis::Horizontal => width,
+                            Axis::Vertical => height,
+                        } as f32;
+                        assert!(dash.start >= 0.0 && dash.end <= extent, "{dash:?}");
+                        assert!(dash.length() > 0.0, "{dash:?}");
+                        let horizontal = dash.axis == Axis::Horizontal;
+                        let line = (horizontal, dash.line_pos.to_bits());
+                        *line_lengths.entry(line).or_default() += dash.length();
+
+                        // No line falls on the far edges, so the dashes that end there are
+                        // only partly on their lines. Nor does one fall on the left edge,
+                        // since the grid is offset, so the same goes for dashes starting there.
+                        if dash.end == extent {
+                            cut_at_end[horizontal as usize] = true;
+                        }
+                        if horizontal && dash.start == 0.0 {
+                            cut_at_start = true;
+                        }
+                    }
+                    for ((horizontal, _), length) in line_lengths {
+                        let extent = if horizontal { width } else { height };
+                        assert!(length <= extent as f32);
+                    }
+                    // Whole stitches reach the ends of their cells, so they are the ones cut
+                    if (dash_ratio, dashes_per_cell) == (1.0, 1) {
+                        let case = format!("spacing {spacing}, alternate {spacing_alt:?}");
+                        assert_eq!(cut_at_end, [true; 2], "{case}");
+                        assert!(cut_at_start, "{case}");
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,98 +1,410 @@
-use nannou::{prelude::*, rand::Rng};
+use std::{
+    collections::VecDeque,
+    env,
+    error::Error,
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    process,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use hitomezashi::{
+    count_loops, downscale_rgba, gradient_position, letterbox_rgba, line_at_or_before,
+    line_distance, load_hito, load_layers_from_png, load_settings_from_svg, parse_batch_csv,
+    pattern_to_ascii, render_layers_rgba, seed_in_density_band, selector_at, selectors_from_bits,
+    selectors_match_seed, selectors_to_bits, settings_from_query, settings_to_query,
+    total_thread_length, write_contact_sheet, write_csv, write_hito, write_jpeg, write_layers_png,
+    write_png, write_png_to, write_seed_sweep_gif, write_svg, write_webp, AspectRatio, Axis,
+    BatchError, BatchRow, ContrastFilter, Corner, Grid, GridLock, LineStyle, LoadError, Pattern,
+    PngColor, RectSettings, Rgb, Stitch, VertDerivation, MAX_DASHES_PER_CELL, MIN_SPACING,
+};
+use nannou::prelude::*;
+use nannou::rand::{Rng, SeedableRng};
 use nannou_egui::{egui, Egui};
 use rand_pcg::Pcg64;
-use rand_seeder::Seeder;
-
-enum ShapeSettings {
-    Rectangle {
-        spacing: f32,
-        horz_selectors: Vec<bool>,
-        vert_selectors: Vec<bool>,
-        horz_seed: u8,
-        vert_seed: u8,
-    },
-    Triangle {
-        spacing: f32,
-        s1_selectors: Vec<bool>,
-        s2_selectors: Vec<bool>,
-        s3_selectors: Vec<bool>,
-        s1_seed: u8,
-        s2_seed: u8,
-        s3_seed: u8,
-    },
-}
-
-impl ShapeSettings {
-    fn new(spacing: f32) -> Self {
-        // By default, create a rectangle
-        // ShapeSettings::Rectangle {
-        //     spacing,
-        //     horz_selectors: vec![false; 10],
-        //     vert_selectors: vec![false; 10],
-        //     horz_seed: 0,
-        //     vert_seed: 0,
-        // }
-        ShapeSettings::Triangle {
-            spacing,
-            s1_selectors: vec![false; 10],
-            s2_selectors: vec![false; 10],
-            s3_selectors: vec![false; 10],
-            s1_seed: 0,
-            s2_seed: 0,
-            s3_seed: 0,
-        }
-    }
-
-    fn display(&self, draw: &Draw, bounds: Rect) {
-        match self {
-            ShapeSettings::Rectangle {
-                spacing,
-                horz_selectors,
-                vert_selectors,
-                horz_seed,
-                vert_seed,
-            } => {
-                draw_hito_vertical(draw, bounds, *spacing, vert_selectors);
-                draw_hito_horizontal(draw, bounds, *spacing, horz_selectors);
-            }
 
-            ShapeSettings::Triangle {
-                spacing,
-                s1_selectors,
-                s2_selectors,
-                s3_selectors,
-                s1_seed,
-                s2_seed,
-                s3_seed,
-            } => {
-                draw_hito_horizontal(draw, bounds, *spacing, s1_selectors);
-                draw_hito_angled(draw, bounds, *spacing, s2_selectors, 60.0);
-                draw_hito_angled(draw, bounds, *spacing, s3_selectors, 120.0);
+/// How many selectors per axis get a checkbox in the "Edit selectors" section
+const MAX_SELECTOR_TOGGLES: usize = 32;
+
+/// The longest side, in pixels, of the preview of the PNG to be saved
+const PREVIEW_SIZE: u32 = 200;
+
+/// How many patterns the history keeps, the longest side of their thumbnails in pixels,
+/// and how long a pattern has to be on screen before it counts as viewed, so that dragging
+/// a seed through many values doesn't fill the history with the ones in between
+const HISTORY_LEN: usize = 8;
+const HISTORY_THUMBNAIL_SIZE: u32 = 64;
+const HISTORY_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// The most cells the statistics count the loops of the exported pattern in, since they
+/// are counted again whenever the statistics window is drawn
+const MAX_LOOP_COUNT_CELLS: usize = 250_000;
+
+/// How many selectors per axis the "Selector bits" section can show as bits
+const BITS_SHOWN_RANGE: RangeInclusive<usize> = 8..=1024;
+
+/// The largest width or height a custom export size can be set to
+const MAX_EXPORT_SIZE: u32 = 16384;
+
+/// How much one notch of the mouse wheel zooms the live view in or out, how far a
+/// touchpad has to scroll to count as a notch, and how far the view can be zoomed in
+const ZOOM_PER_SCROLL_LINE: f32 = 1.1;
+const PIXELS_PER_SCROLL_LINE: f32 = 40.0;
+const MAX_ZOOM: f32 = 20.0;
+
+/// The range of the spacing sliders, in pixels
+const SPACING_RANGE: RangeInclusive<f32> = 2.0..=500.0;
+
+/// The range of the slider for how many selectors each axis repeats after
+const SELECTOR_LEN_RANGE: RangeInclusive<usize> = 2..=256;
+
+/// How many lines the view can draw before the settings warn that it may be slow
+const DENSE_LINE_COUNT: i64 = 1000;
+
+/// The largest width or height `--serve` renders, to keep each request quick and small
+const MAX_SERVE_SIZE: u32 = 4096;
+
+/// The largest seed "Surprise me" picks, to keep the seeds short enough to read and type
+const MAX_SURPRISE_SEED: u64 = 9999;
+
+struct Model {
+    /// The patterns drawn on top of each other, from the bottom up. There is always at
+    /// least one.
+    layers: Vec<RectSettings>,
+    /// The index of the layer the settings window edits
+    active_layer: usize,
+    egui: Egui,
+    /// The print resolution used to estimate how much thread a pattern needs
+    px_per_mm: f32,
+    /// The size to export patterns at (before `grid_lock` and the legend), which follows
+    /// the window unless a custom size is set
+    custom_export_size: bool,
+    export_width: u32,
+    export_height: u32,
+    /// The quality (1 to 100) of JPEG exports
+    jpeg_quality: u8,
+    /// Which seed the exported GIF sweeps through, and how fast
+    gif_axis: Axis,
+    gif_fps: u16,
+    /// How many tiles along each side a contact sheet has, and how big each tile is
+    sheet_count: u32,
+    sheet_tile_size: u32,
+    /// The size of the exported stitch chart in cells, which follows the export size unless
+    /// a custom size is set
+    custom_chart_size: bool,
+    chart_cols: usize,
+    chart_rows: usize,
+    /// The selector focused for keyboard editing, if any
+    cursor: Option<SelectorCursor>,
+    /// How many selectors per axis are shown as bits, and the bits typed in to replace the
+    /// horizontal and vertical selectors
+    bits_shown: usize,
+    bits_input: [String; 2],
+    /// The seed that "Surprise me" starts drawing its settings from, and the generator it
+    /// draws them from, so that starting again from the same seed gives the same surprises
+    /// in the same order
+    surprise_seed: u64,
+    surprise_rng: Pcg64,
+    /// The fraction of true selectors that "Randomize within density band" aims for
+    min_density: f32,
+    max_density: f32,
+    /// Why the last attempt to load, save, copy, or paste settings failed, shown until
+    /// dismissed
+    error: Option<String>,
+    /// Something worth knowing about the last pattern loaded from a file, shown until
+    /// dismissed
+    notice: Option<String>,
+    /// The system clipboard, opened the first time settings are copied or pasted
+    clipboard: Option<arboard::Clipboard>,
+    /// The longest side of images copied to the clipboard. Bigger exports are shrunk to it.
+    copy_size: u32,
+    /// While a PNG is being saved in the background, the channel that says when it's done,
+    /// and why it failed if it did
+    saving: Option<mpsc::Receiver<Result<(), String>>>,
+    /// While GIFs or contact sheets are being written in the background, the channels that
+    /// say when each is done, and why it failed if it did
+    exports: Vec<mpsc::Receiver<Result<(), String>>>,
+    /// While a batch file is being rendered in the background, the channel that says how
+    /// each of its lines went, with the line's number. Also how many lines there are to
+    /// render and how many are done, and why the ones that failed did.
+    batch: Option<mpsc::Receiver<(usize, Result<(), String>)>>,
+    batch_total: usize,
+    batch_done: usize,
+    batch_failures: Vec<String>,
+    /// Whether to show a small render of the PNG that would be saved, and the texture it
+    /// was last rendered into
+    show_preview: bool,
+    preview: Option<Preview>,
+    /// How the preview is seen, to check the pattern reads for low-vision viewers
+    preview_filter: ContrastFilter,
+    /// The settings and size the preview was last rendered for (see `preview_key`), so it
+    /// is only rendered again once they change
+    preview_key: String,
+    /// While the preview is being rendered in the background, the channel it arrives on
+    preview_job: Option<mpsc::Receiver<PreviewPixels>>,
+    /// The patterns viewed most recently, newest first, to go back to from the "History"
+    /// window
+    history: VecDeque<HistoryEntry>,
+    /// The settings of the layer being edited, and since when its seeds have been what
+    /// they are, so that it can be added to the history once they change
+    viewed: Option<(RectSettings, Instant)>,
+    /// The named settings saved in `presets_path`, and the name to save the next one under
+    presets: Vec<(String, RectSettings)>,
+    preset_name: String,
+    /// How much the live view is magnified, and how far it is moved, in window points. The
+    /// exports ignore these.
+    zoom: f32,
+    pan: Vec2,
+    /// Where the mouse was when the view was last panned, while it is being dragged
+    drag_from: Option<Point2>,
+    /// The cell of a square grid that the mouse is over, if it isn't over a settings window
+    hovered_cell: Option<GridCell>,
+}
+
+/// An image shown in a window, such as the preview of the PNG to be saved or a thumbnail
+/// in the history, as an egui texture
+struct Preview {
+    texture: egui::TextureId,
+    size: egui::Vec2,
+}
+
+/// A pattern in the history, and its thumbnail
+struct HistoryEntry {
+    settings: RectSettings,
+    thumbnail: Preview,
+}
+
+/// The pixels of a preview, and how many there are across and down
+type PreviewPixels = (Vec<egui::Color32>, (usize, usize));
+
+/// A keyboard focus on one selector. The selectors are treated as a grid of two rows:
+/// the horizontal selectors on top and the vertical selectors below.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SelectorCursor {
+    axis: Axis,
+    index: usize,
+}
+
+impl SelectorCursor {
+    /// Move the cursor in response to `key`, wrapping around at the edges of the grid.
+    /// Left/Right move along the focused row and Up/Down move between the rows. Returns
+    /// false if `key` doesn't move the cursor.
+    fn move_with_key(&mut self, key: Key, settings: &RectSettings) -> bool {
+        match key {
+            Key::Left | Key::Right => {
+                let len = settings.selectors(self.axis).len();
+                if len == 0 {
+                    return true;
+                }
+                self.index = if key == Key::Left {
+                    (self.index + len - 1) % len
+                } else {
+                    (self.index + 1) % len
+                };
+            }
+            Key::Up | Key::Down => {
+                // With only two rows, moving up or down always lands on the other row
+                self.axis = match self.axis {
+                    Axis::Horizontal => Axis::Vertical,
+                    Axis::Vertical => Axis::Horizontal,
+                };
+                let len = settings.selectors(self.axis).len();
+                self.index = self.index.min(len.saturating_sub(1));
             }
+            _ => return false,
         }
+        true
     }
 }
 
-struct RectSettings {
-    spacing: f32,
-    horz_selectors: Vec<bool>,
-    vert_selectors: Vec<bool>,
-    horz_seed: u8,
-    vert_seed: u8,
+/// One cell of a square grid, between horizontal lines `row` and `row + 1` and vertical
+/// lines `col` and `col + 1` of the live view (see `line_distance`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct GridCell {
+    col: i64,
+    row: i64,
 }
 
-struct Model {
-    settings: ShapeSettings,
-    egui: Egui,
+impl GridCell {
+    /// The cell of the live view that holds `point`, before any zoom or pan, when the
+    /// pattern fills `bounds`
+    fn at(settings: &RectSettings, bounds: Rect, point: Point2) -> Self {
+        let (offset_x, offset_y) = settings.grid_offset();
+        GridCell {
+            col: line_at_or_before(
+                settings.line_gaps(Axis::Vertical),
+                offset_x,
+                point.x - bounds.left(),
+            ),
+            row: line_at_or_before(
+                settings.line_gaps(Axis::Horizontal),
+                offset_y,
+                bounds.top() - point.y,
+            ),
+        }
+    }
+
+    /// The area of the cell within `bounds`
+    fn rect(self, settings: &RectSettings, bounds: Rect) -> Rect {
+        let (horz_gaps, vert_gaps) = (
+            settings.line_gaps(Axis::Horizontal),
+            settings.line_gaps(Axis::Vertical),
+        );
+        let (offset_x, offset_y) = settings.grid_offset();
+        let left = bounds.left() + offset_x + line_distance(vert_gaps, self.col);
+        let right = bounds.left() + offset_x + line_distance(vert_gaps, self.col + 1);
+        let top = bounds.top() - offset_y - line_distance(horz_gaps, self.row);
+        let bottom = bounds.top() - offset_y - line_distance(horz_gaps, self.row + 1);
+        Rect::from_corners(pt2(left, top), pt2(right, bottom))
+    }
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(port) = args.iter().position(|arg| arg == "--serve") {
+        let result = match args.get(port + 1).map(|port| port.parse()) {
+            Some(Ok(port)) => serve(port),
+            _ => Err("--serve needs a port number".into()),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to serve: {e}");
+            process::exit(1);
+        }
+        return;
+    }
+    if args.iter().any(|arg| arg == "--render") {
+        match render_from_args(&args) {
+            Ok(path) => println!("{}", path.display()),
+            Err(e) => {
+                eprintln!("Failed to render: {e}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
     nannou::app(model)
         .loop_mode(LoopMode::Wait)
         .update(update)
         .run();
 }
 
+/// Render a PNG without opening a window, as described by the `--render` command line:
+///
+/// `--render --out <path> [--width <px>] [--height <px>] [--spacing <px>]
+/// [--horz-seed <seed>] [--vert-seed <seed>]`
+///
+/// Returns the path the PNG was written to.
+fn render_from_args(args: &[String]) -> Result<PathBuf, Box<dyn Error>> {
+    // The same size as the default window
+    let (mut width, mut height) = (1024, 768);
+    let mut settings = RectSettings::new(25.0);
+    let mut out = None;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        if flag == "--render" {
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing a value for {flag}"))?;
+        let invalid = || format!("Invalid value for {flag}: {value}");
+        match flag.as_str() {
+            "--width" => width = value.parse().map_err(|_| invalid())?,
+            "--height" => height = value.parse().map_err(|_| invalid())?,
+            "--spacing" => {
+                // Like `settings_from_query`, reject spacings that would be raised to the
+                // minimum, rather than render something other than what was asked for
+                let spacing = match value.parse::<f32>() {
+                    Ok(spacing) if spacing.is_finite() && spacing >= MIN_SPACING => spacing,
+                    _ => return Err(invalid().into()),
+                };
+                settings.horz_spacing = spacing;
+                settings.vert_spacing = spacing;
+            }
+            "--horz-seed" => settings.horz_seed = value.parse().map_err(|_| invalid())?,
+            "--vert-seed" => settings.vert_seed = value.parse().map_err(|_| invalid())?,
+            "--out" => out = Some(PathBuf::from(value)),
+            _ => return Err(format!("Unknown option {flag}").into()),
+        }
+    }
+    let out = out.ok_or("Missing --out <path>")?;
+    if width == 0 || height == 0 {
+        return Err("The width and height must be at least 1".into());
+    }
+
+    settings.fit_selectors(width, height);
+    settings.regenerate_selectors();
+    let (width, height) = settings.export_size(width, height);
+    write_png(&out, &settings, width, height)?;
+    Ok(out)
+}
+
+/// Serve freshly rendered patterns over HTTP on `port`, until the process is stopped.
+/// `GET /pattern.png` answers with a PNG built from its query string (see `serve_query`).
+fn serve(port: u16) -> Result<(), Box<dyn Error>> {
+    let server = tiny_http::Server::http(("0.0.0.0", port)).map_err(|e| e.to_string())?;
+    println!("Serving patterns on port {port}");
+
+    for request in server.incoming_requests() {
+        let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+        let response = if request.method() != &tiny_http::Method::Get {
+            tiny_http::Response::from_string("Only GET is supported").with_status_code(405)
+        } else if path != "/pattern.png" {
+            tiny_http::Response::from_string("Not found").with_status_code(404)
+        } else {
+            match serve_query(query) {
+                Ok(png) => {
+                    let content_type = tiny_http::Header::from_bytes("Content-Type", "image/png")
+                        .expect("The header is valid ASCII");
+                    tiny_http::Response::from_data(png).with_header(content_type)
+                }
+                Err(e) => tiny_http::Response::from_string(e).with_status_code(400),
+            }
+        };
+        if let Err(e) = request.respond(response) {
+            eprintln!("Failed to send a response: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Render the PNG asked for by a `--serve` query string. It takes the settings that
+/// `settings_from_query` reads, and the `w` and `h` of the image, up to `MAX_SERVE_SIZE`.
+/// Anything else is an error.
+fn serve_query(query: &str) -> Result<Vec<u8>, String> {
+    let (mut width, mut height) = (1024, 768);
+    let mut settings_params = Vec::new();
+
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (key, value) = param.split_once('=').unwrap_or((param, ""));
+        let invalid = || format!("Invalid value for {key}: {value}");
+        match key {
+            "w" => width = value.parse().map_err(|_| invalid())?,
+            "h" => height = value.parse().map_err(|_| invalid())?,
+            _ => settings_params.push(param),
+        }
+    }
+    let mut settings =
+        settings_from_query(&settings_params.join("&")).map_err(|e| e.to_string())?;
+    if !(1..=MAX_SERVE_SIZE).contains(&width) || !(1..=MAX_SERVE_SIZE).contains(&height) {
+        return Err(format!(
+            "The width and height must be between 1 and {MAX_SERVE_SIZE}"
+        ));
+    }
+
+    // Unlike regenerating them, this leaves any selectors the query gave alone
+    settings.fit_selectors(width, height);
+    let (width, height) = settings.export_size(width, height);
+    let mut png = Vec::new();
+    write_png_to(&mut png, &settings, width, height).map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
 fn model(app: &App) -> Model {
     let window_id = app
         .new_window()
@@ -103,286 +415,1967 @@ fn model(app: &App) -> Model {
 
     let window = app.window(window_id).unwrap();
     let egui = Egui::from_window(&window);
+    // Each session starts from a new surprise seed, which can be set back to replay it
+    let surprise_seed = nannou::rand::thread_rng().gen_range(0..=MAX_SURPRISE_SEED);
     Model {
         egui,
-        settings: ShapeSettings::new(25.0),
+        layers: vec![RectSettings::new(25.0)],
+        active_layer: 0,
+        // 96 pixels per inch
+        px_per_mm: 96.0 / 25.4,
+        custom_export_size: false,
+        export_width: window.rect().w() as u32,
+        export_height: window.rect().h() as u32,
+        jpeg_quality: 90,
+        gif_axis: Axis::Horizontal,
+        gif_fps: 10,
+        sheet_count: 4,
+        sheet_tile_size: 128,
+        custom_chart_size: false,
+        chart_cols: 32,
+        chart_rows: 32,
+        cursor: None,
+        bits_shown: 64,
+        bits_input: Default::default(),
+        surprise_seed,
+        surprise_rng: Pcg64::seed_from_u64(surprise_seed),
+        min_density: 0.4,
+        max_density: 0.6,
+        error: None,
+        notice: None,
+        clipboard: None,
+        copy_size: 1024,
+        saving: None,
+        exports: Vec::new(),
+        batch: None,
+        batch_total: 0,
+        batch_done: 0,
+        batch_failures: Vec::new(),
+        history: VecDeque::new(),
+        viewed: None,
+        presets: load_presets(),
+        preset_name: String::new(),
+        zoom: 1.0,
+        pan: Vec2::ZERO,
+        drag_from: None,
+        hovered_cell: None,
+        show_preview: false,
+        preview_filter: ContrastFilter::Off,
+        preview: None,
+        preview_key: String::new(),
+        preview_job: None,
     }
 }
 
-fn raw_window_event(_app: &App, model: &mut Model, event: &nannou::winit::event::WindowEvent) {
+/// Ask where to save a PNG of the pattern at `export_size`, and write it on a background
+/// thread, which `saving` is set to hear back from. This is what both the "Save as PNG"
+/// button and Ctrl+S do. Nothing happens if a save is already running.
+fn begin_save(
+    layers: &[RectSettings],
+    export_size: (u32, u32),
+    window_size: (u32, u32),
+    saving: &mut Option<mpsc::Receiver<Result<(), String>>>,
+) {
+    if saving.is_some() {
+        return;
+    }
+    let mut dialog = rfd::FileDialog::new().add_filter("PNG", &["png"]);
+    if layers[0].auto_name {
+        dialog = dialog.set_file_name(layers[0].file_name());
+    }
+    if let Some(path) = dialog.save_file() {
+        let mut layers = layers.to_vec();
+        // Only the bottom layer's window size is used when loading, like its other settings
+        // for the whole image
+        (layers[0].window_width, layers[0].window_height) =
+            (Some(window_size.0), Some(window_size.1));
+        let (sender, receiver) = mpsc::channel();
+        // Large exports take a while, so keep the window responsive
+        thread::spawn(move || {
+            let result = save_png_result(export_size, &layers, &path);
+            if result.is_ok() {
+                println!("Saved {}", path.display());
+            }
+            // The window may have closed already, which is fine
+            let _ = sender.send(result);
+        });
+        *saving = Some(receiver);
+    }
+}
+
+/// Write a `what` (like "GIF") to `path` with `export` on a background thread, since the
+/// exports that render many patterns take a while. The channel returned says when it's
+/// done, and why it failed if it did.
+fn begin_export(
+    what: &'static str,
+    path: PathBuf,
+    export: impl FnOnce(&Path) -> Result<(), Box<dyn Error>> + Send + 'static,
+) -> mpsc::Receiver<Result<(), String>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let result = export(&path).map_err(|e| format!("Failed to create {what}: {e}"));
+        if result.is_ok() {
+            println!("Saved {}", path.display());
+        }
+        // The window may have closed already, which is fine
+        let _ = sender.send(result);
+    });
+    receiver
+}
+
+/// Render the preview of the PNG that would be saved, in the background, whenever the
+/// `layers`, the `export_size` or the `filter` it is seen through have changed since it was
+/// last rendered. Only one preview is rendered at a time, so while settings keep changing
+/// it catches up with them rather than rendering on every frame. Once a render is done, it
+/// replaces the preview's texture.
+#[allow(clippy::too_many_arguments)]
+fn update_preview(
+    app: &App,
+    egui: &mut Egui,
+    layers: &[RectSettings],
+    export_size: (u32, u32),
+    filter: ContrastFilter,
+    preview: &mut Option<Preview>,
+    preview_key: &mut String,
+    preview_job: &mut Option<mpsc::Receiver<PreviewPixels>>,
+) {
+    if let Some(receiver) = preview_job {
+        match receiver.try_recv() {
+            Ok((pixels, size)) => {
+                egui.with_epi_frame(app.create_proxy(), |_, frame| {
+                    let textures = frame.tex_allocator();
+                    if let Some(old) = preview.take() {
+                        textures.free(old.texture);
+                    }
+                    *preview = Some(Preview {
+                        texture: textures.alloc_srgba_premultiplied(size, &pixels),
+                        size: egui::vec2(size.0 as f32, size.1 as f32),
+                    });
+                });
+                *preview_job = None;
+            }
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => *preview_job = None,
+        }
+    }
+
+    let key = preview_key_for(layers, export_size, filter);
+    if key == *preview_key {
+        return;
+    }
+    *preview_key = key;
+
+    let layers = layers.to_vec();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let (buffer, width, height) = render_png_image(&layers, export_size);
+        let (mut small, small_width, small_height) =
+            downscale_rgba(&buffer, width, height, PREVIEW_SIZE);
+        filter.apply_rgba(&mut small);
+        let pixels = small
+            .chunks_exact(4)
+            .map(|p| egui::Color32::from_rgba_unmultiplied(p[0], p[1], p[2], p[3]))
+            .collect();
+        // The window may have closed already, which is fine
+        let _ = sender.send((pixels, (small_width as usize, small_height as usize)));
+    });
+    *preview_job = Some(receiver);
+}
+
+/// Add the pattern that was on screen to the front of the `history` once the seeds of
+/// `settings`, the layer being edited, change, if it was on screen for long enough. Going
+/// back to a pattern that is already in the history moves it to the front.
+fn update_history(
+    app: &App,
+    egui: &mut Egui,
+    settings: &RectSettings,
+    window_size: (u32, u32),
+    history: &mut VecDeque<HistoryEntry>,
+    viewed: &mut Option<(RectSettings, Instant)>,
+) {
+    let seeds = |settings: &RectSettings| (settings.horz_seed, settings.vert_seed);
+    if let Some((shown, _)) = viewed {
+        if seeds(shown) == seeds(settings) {
+            // Keep up with any other changes, so the history has the pattern as last seen
+            shown.clone_from(settings);
+            return;
+        }
+    }
+
+    if let Some((shown, since)) = viewed.replace((settings.clone(), Instant::now())) {
+        if since.elapsed() < HISTORY_DEBOUNCE {
+            return;
+        }
+        let (width, height) = window_size;
+        let buffer = render_layers_rgba(width, height, std::slice::from_ref(&shown));
+        let (small, small_width, small_height) =
+            downscale_rgba(&buffer, width, height, HISTORY_THUMBNAIL_SIZE);
+        let pixels: Vec<_> = small
+            .chunks_exact(4)
+            .map(|p| egui::Color32::from_rgba_unmultiplied(p[0], p[1], p[2], p[3]))
+            .collect();
+        let size = (small_width as usize, small_height as usize);
+
+        egui.with_epi_frame(app.create_proxy(), |_, frame| {
+            let textures = frame.tex_allocator();
+            let json = shown.to_json();
+            history.retain(|entry| {
+                let keep = entry.settings.to_json() != json;
+                if !keep {
+                    textures.free(entry.thumbnail.texture);
+                }
+                keep
+            });
+            history.push_front(HistoryEntry {
+                settings: shown,
+                thumbnail: Preview {
+                    texture: textures.alloc_srgba_premultiplied(size, &pixels),
+                    size: egui::vec2(size.0 as f32, size.1 as f32),
+                },
+            });
+            while history.len() > HISTORY_LEN {
+                let oldest = history
+                    .pop_back()
+                    .expect("The history is longer than its limit");
+                textures.free(oldest.thumbnail.texture);
+            }
+        });
+    }
+}
+
+/// The image that saving the `layers` as a PNG at `export_size` writes, and its size. It is
+/// rendered at the full export size and letterboxed like in `write_layers_png`, so the
+/// preview and copied images are the saved PNG. Like `render_layers_rgba`, this panics if
+/// there are no `layers`, which the window always has.
+fn render_png_image(layers: &[RectSettings], export_size: (u32, u32)) -> (Vec<u8>, u32, u32) {
+    debug_assert!(!layers.is_empty(), "There has to be at least one layer");
+    let settings = &layers[0];
+    let (width, height) = settings.export_size(export_size.0, export_size.1);
+    let buffer = render_layers_rgba(width, height, layers);
+    letterbox_rgba(
+        buffer,
+        width,
+        height,
+        settings.aspect_ratio,
+        settings.background_color,
+    )
+}
+
+/// Render a PNG into `out_dir` for each of the `rows` of a batch file, with the seeds and
+/// spacing from the row and everything else from `settings`, on a background thread. The
+/// returned channel says how each line went, including the ones that couldn't be read.
+fn begin_batch(
+    rows: Vec<(usize, Result<BatchRow, BatchError>)>,
+    settings: &RectSettings,
+    export_size: (u32, u32),
+    out_dir: &Path,
+) -> mpsc::Receiver<(usize, Result<(), String>)> {
+    let (settings, out_dir) = (settings.clone(), out_dir.to_owned());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for (line, row) in rows {
+            let result = row.map_err(|e| e.to_string()).and_then(|row| {
+                let mut settings = row.apply(&settings);
+                settings.fit_selectors(export_size.0, export_size.1);
+                let path = out_dir.join(row.file_name());
+                save_png_result(export_size, &[settings], &path)?;
+                println!("Saved {}", path.display());
+                Ok(())
+            });
+            // The window may have closed already, which is fine
+            if sender.send((line, result)).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+/// Everything the preview of the PNG depends on: the size, the filter it is seen through,
+/// and the settings of every layer
+fn preview_key_for(
+    layers: &[RectSettings],
+    export_size: (u32, u32),
+    filter: ContrastFilter,
+) -> String {
+    let mut key = format!("{}x{} {}", export_size.0, export_size.1, filter.label());
+    for layer in layers {
+        key.push_str(&layer.to_json());
+    }
+    key
+}
+
+/// Save the PNG like `create_image_with_pattern`, with any error turned into the message to
+/// show for it
+fn save_png_result(
+    export_size: (u32, u32),
+    layers: &[RectSettings],
+    path: &Path,
+) -> Result<(), String> {
+    create_image_with_pattern(export_size, layers, path)
+        .map_err(|e| format!("Failed to save {}: {e}", path.display()))
+}
+
+fn raw_window_event(app: &App, model: &mut Model, event: &nannou::winit::event::WindowEvent) {
     model.egui.handle_raw_event(event);
+    handle_view_event(app, model, event);
+
+    // Leave the keyboard to egui while it is using it, e.g. for typing in a text field
+    if model.egui.ctx().wants_keyboard_input() {
+        return;
+    }
+
+    if let nannou::winit::event::WindowEvent::KeyboardInput {
+        input:
+            nannou::winit::event::KeyboardInput {
+                state: nannou::winit::event::ElementState::Pressed,
+                virtual_keycode: Some(key),
+                ..
+            },
+        ..
+    } = event
+    {
+        if *key == Key::Key0 {
+            model.zoom = 1.0;
+            model.pan = Vec2::ZERO;
+        } else if *key == Key::S && (app.keys.mods.ctrl() || app.keys.mods.logo()) {
+            let export_size = (model.export_width, model.export_height);
+            let window_size = (app.window_rect().w() as u32, app.window_rect().h() as u32);
+            begin_save(&model.layers, export_size, window_size, &mut model.saving);
+        } else {
+            handle_cursor_key(model, *key);
+        }
+    }
+}
+
+/// Zoom the live view with the mouse wheel, keeping the point under the mouse in place,
+/// and pan it by dragging with the left mouse button. The mouse is left to egui while it
+/// is over the settings window.
+fn handle_view_event(app: &App, model: &mut Model, event: &nannou::winit::event::WindowEvent) {
+    use nannou::winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+
+    let mouse = app.mouse.position();
+    let wants_pointer = model.egui.ctx().wants_pointer_input();
+    match event {
+        WindowEvent::MouseWheel { delta, .. } if !wants_pointer => {
+            let lines = match delta {
+                MouseScrollDelta::LineDelta(_, y) => *y,
+                MouseScrollDelta::PixelDelta(position) => {
+                    position.y as f32 / PIXELS_PER_SCROLL_LINE
+                }
+            };
+            let zoom = (model.zoom * ZOOM_PER_SCROLL_LINE.powf(lines)).clamp(1.0, MAX_ZOOM);
+            model.pan = mouse - (mouse - model.pan) * (zoom / model.zoom);
+            model.zoom = zoom;
+        }
+        WindowEvent::MouseInput {
+            state,
+            button: MouseButton::Left,
+            ..
+        } => {
+            model.drag_from = match state {
+                ElementState::Pressed if !wants_pointer => Some(mouse),
+                _ => None,
+            };
+        }
+        WindowEvent::CursorMoved { .. } => {
+            if let Some(from) = model.drag_from.as_mut() {
+                model.pan += mouse - *from;
+                *from = mouse;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Move or act on the keyboard selector cursor. The arrow keys move it (starting it at
+/// the first horizontal selector if there is no cursor yet), Space or Enter toggle the
+/// focused selector, and Escape removes the cursor.
+fn handle_cursor_key(model: &mut Model, key: Key) {
+    let settings = &mut model.layers[model.active_layer];
+    match (key, model.cursor.as_mut()) {
+        (Key::Escape, _) => model.cursor = None,
+        (Key::Space | Key::Return, Some(cursor)) => {
+            let SelectorCursor { axis, index } = *cursor;
+            if index < settings.selectors(axis).len() {
+                settings.toggle_selector(axis, index);
+            }
+        }
+        (Key::Left | Key::Right | Key::Up | Key::Down, None) => {
+            model.cursor = Some(SelectorCursor {
+                axis: Axis::Horizontal,
+                index: 0,
+            });
+        }
+        (_, Some(cursor)) => {
+            cursor.move_with_key(key, settings);
+        }
+        _ => {}
+    }
 }
 
-fn update(_app: &App, model: &mut Model, update: Update) {
+fn update(app: &App, model: &mut Model, update: Update) {
     // This destructures the model, giving mutable references to the settings and egui
     // but without having to prefix them with `model` every time they are accessed.
     let Model {
-        ref mut settings,
+        ref mut layers,
+        ref mut active_layer,
         ref mut egui,
+        ref mut px_per_mm,
+        ref mut custom_export_size,
+        ref mut export_width,
+        ref mut export_height,
+        ref mut jpeg_quality,
+        ref mut gif_axis,
+        ref mut gif_fps,
+        ref mut sheet_count,
+        ref mut sheet_tile_size,
+        ref mut custom_chart_size,
+        ref mut chart_cols,
+        ref mut chart_rows,
+        ref mut bits_shown,
+        ref mut bits_input,
+        ref mut surprise_seed,
+        ref mut surprise_rng,
+        ref mut min_density,
+        ref mut max_density,
+        ref mut error,
+        ref mut notice,
+        ref mut clipboard,
+        ref mut copy_size,
+        ref mut saving,
+        ref mut exports,
+        ref mut batch,
+        ref mut batch_total,
+        ref mut batch_done,
+        ref mut batch_failures,
+        ref mut show_preview,
+        ref mut preview_filter,
+        ref mut preview,
+        ref mut preview_key,
+        ref mut preview_job,
+        ref mut history,
+        ref mut viewed,
+        ref mut presets,
+        ref mut preset_name,
+        ref mut hovered_cell,
+        cursor,
+        zoom,
+        pan,
+        ..
     } = *model;
 
+    // Check whether the PNG being saved in the background is done. If the saving thread
+    // panicked, the channel is disconnected instead, which also means it is over.
+    if let Some(receiver) = saving {
+        match receiver.try_recv() {
+            Ok(result) => {
+                if let Err(e) = result {
+                    *error = Some(e);
+                }
+                *saving = None;
+            }
+            Err(mpsc::TryRecvError::Empty) => {}
+            Err(mpsc::TryRecvError::Disconnected) => {
+                *error = Some("Saving the PNG stopped before it was done".to_string());
+                *saving = None;
+            }
+        }
+    }
+
+    // Likewise for the GIFs and contact sheets, keeping the channels of those still running
+    exports.retain(|receiver| match receiver.try_recv() {
+        Ok(result) => {
+            if let Err(e) = result {
+                *error = Some(e);
+            }
+            false
+        }
+        Err(mpsc::TryRecvError::Empty) => true,
+        Err(mpsc::TryRecvError::Disconnected) => {
+            *error = Some("Exporting stopped before it was done".to_string());
+            false
+        }
+    });
+
+    // Like the PNG, a batch reports each line as it is rendered
+    if let Some(receiver) = batch {
+        loop {
+            match receiver.try_recv() {
+                Ok((line, result)) => {
+                    *batch_done += 1;
+                    if let Err(e) = result {
+                        batch_failures.push(format!("Line {line}: {e}"));
+                    }
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    if *batch_done < *batch_total {
+                        batch_failures.push("The batch stopped before it was done".to_string());
+                    }
+                    if !batch_failures.is_empty() {
+                        *error = Some(format!(
+                            "{} of {} patterns in the batch failed:\n{}",
+                            batch_failures.len(),
+                            *batch_total,
+                            batch_failures.join("\n")
+                        ));
+                    }
+                    *batch = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    let bounds = app.window_rect();
+    if !*custom_export_size {
+        *export_width = bounds.w() as u32;
+        *export_height = bounds.h() as u32;
+    }
+    // Make sure there are enough selectors for both the window and the export
+    for layer in layers.iter_mut() {
+        layer.fit_selectors(
+            (bounds.w() as u32).max(*export_width),
+            (bounds.h() as u32).max(*export_height),
+        );
+    }
+
+    if *show_preview {
+        let export_size = (*export_width, *export_height);
+        update_preview(
+            app,
+            egui,
+            layers,
+            export_size,
+            *preview_filter,
+            preview,
+            preview_key,
+            preview_job,
+        );
+    }
+
+    let window_size = (bounds.w() as u32, bounds.h() as u32);
+    update_history(
+        app,
+        egui,
+        &layers[*active_layer],
+        window_size,
+        history,
+        viewed,
+    );
+
     egui.set_elapsed_time(update.since_start);
     let ctx = egui.begin_frame();
+    egui::Window::new("Layers").show(&ctx, |ui| {
+        egui::ComboBox::from_label("Editing")
+            .selected_text(format!("Layer {}", *active_layer + 1))
+            .show_ui(ui, |ui| {
+                for index in 0..layers.len() {
+                    ui.selectable_value(active_layer, index, format!("Layer {}", index + 1));
+                }
+            });
+        ui.horizontal(|ui| {
+            if ui.button("Add layer").clicked() {
+                // Start from the layer being edited, but with its own seeds and colors so
+                // the new layer stands out from it
+                let mut rng = nannou::rand::thread_rng();
+                let mut layer = layers[*active_layer].clone();
+                layer.horz_seed = rng.gen_range(0..=MAX_SURPRISE_SEED);
+                layer.vert_seed = rng.gen_range(0..=MAX_SURPRISE_SEED);
+                layer.diag_seed = rng.gen_range(0..=MAX_SURPRISE_SEED);
+                for color in [&mut layer.horz_color, &mut layer.vert_color] {
+                    *color = Rgb::new(rng.gen(), rng.gen(), rng.gen());
+                }
+                layer.edited = false;
+                layer.regenerate_selectors();
+                layers.push(layer);
+                *active_layer = layers.len() - 1;
+            }
+            let removable = layers.len() > 1;
+            if ui
+                .add_enabled(removable, egui::Button::new("Remove layer"))
+                .clicked()
+            {
+                layers.remove(*active_layer);
+                *active_layer = active_layer.saturating_sub(1);
+            }
+        });
+    });
+
+    if !history.is_empty() {
+        egui::Window::new("History").show(&ctx, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                for entry in history.iter() {
+                    let thumbnail = &entry.thumbnail;
+                    let seeds = (entry.settings.horz_seed, entry.settings.vert_seed);
+                    if ui
+                        .add(egui::ImageButton::new(thumbnail.texture, thumbnail.size))
+                        .on_hover_text(format!("Seeds {}, {}", seeds.0, seeds.1))
+                        .clicked()
+                    {
+                        layers[*active_layer] = entry.settings.clone();
+                        layers[*active_layer].dirty = true;
+                    }
+                }
+            });
+        });
+    }
+
+    // Loading a PNG replaces every layer, and saving or copying one, or inverting the colors,
+    // needs all of them, so they wait until the settings window is done editing the active
+    // layer
+    let mut loaded_layers = None;
+    let mut save_png = false;
+    let mut copy_image = false;
+    let mut invert_colors = false;
+    let settings = &mut layers[*active_layer];
     egui::Window::new("Settings").show(&ctx, |ui| {
+        ui.add(
+            egui::Slider::new(&mut settings.horz_spacing, SPACING_RANGE)
+                .logarithmic(true)
+                .text("Horizontal line spacing"),
+        );
+        ui.add(
+            egui::Slider::new(&mut settings.vert_spacing, SPACING_RANGE)
+                .logarithmic(true)
+                .text("Vertical line spacing"),
+        );
+        ui.horizontal(|ui| {
+            let mut alternate = settings.spacing_alt.is_some();
+            if ui.checkbox(&mut alternate, "Alternate spacing").changed() {
+                settings.spacing_alt = alternate.then_some(settings.horz_spacing / 2.0);
+            }
+            if let Some(alt) = &mut settings.spacing_alt {
+                ui.add(
+                    egui::Slider::new(alt, SPACING_RANGE)
+                        .logarithmic(true)
+                        .text("Every other gap"),
+                );
+            }
+        });
+        ui.checkbox(
+            &mut settings.snap_to_pixels,
+            "Snap spacing to whole pixels (moves lines slightly)",
+        );
+        // Every dash is drawn separately, so very dense patterns take a while to redraw
+        let pattern = Pattern::new(settings, bounds.w() as u32, bounds.h() as u32);
+        let lines: i64 = [Axis::Horizontal, Axis::Vertical]
+            .map(|axis| pattern.line_numbers(axis))
+            .into_iter()
+            .map(|lines| lines.end - lines.start)
+            .sum();
+        if lines > DENSE_LINE_COUNT {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                format!("{lines} lines: the view may be slow to redraw"),
+            );
+        }
+        // Shifting by a whole spacing or more just repeats the pattern
+        let (horz_spacing, vert_spacing) = (settings.horz_spacing, settings.vert_spacing);
+        ui.add(
+            egui::Slider::new(&mut settings.offset_x, -vert_spacing..=vert_spacing)
+                .text("Horizontal offset"),
+        );
+        ui.add(
+            egui::Slider::new(&mut settings.offset_y, -horz_spacing..=horz_spacing)
+                .text("Vertical offset"),
+        );
+        ui.add(
+            egui::Slider::new(&mut settings.margin, 0.0..=200.0)
+                .max_decimals(0)
+                .text("Margin"),
+        );
+
         let mut changed = false;
-        // changed |= ui
-        //     .add(egui::Slider::new(&mut settings.spacing, 10.0..=100.0).text("Spacing"))
-        //     .changed();
+        let mut regenerate = false;
+        ui.horizontal(|ui| {
+            changed |= ui
+                .add(egui::DragValue::new(&mut settings.horz_seed))
+                .changed();
+            ui.label("Horizontal Seed");
+        });
+        ui.horizontal(|ui| {
+            changed |= ui
+                .add(egui::DragValue::new(&mut settings.vert_seed))
+                .changed();
+            ui.label("Vertical Seed");
+        });
+        egui::ComboBox::from_label("Grid")
+            .selected_text(settings.grid.label())
+            .show_ui(ui, |ui| {
+                for grid in Grid::ALL {
+                    changed |= ui
+                        .selectable_value(&mut settings.grid, grid, grid.label())
+                        .changed();
+                }
+            });
+        if settings.grid == Grid::Triangular {
+            ui.horizontal(|ui| {
+                changed |= ui
+                    .add(egui::DragValue::new(&mut settings.diag_seed))
+                    .changed();
+                ui.label("Diagonal Seed");
+            });
+        }
 
-        // changed |= ui
-        //     .add(egui::Slider::new(&mut settings.horz_seed, 0..=255).text("Horizontal Seed"))
-        //     .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.horz_bias, 0.0..=1.0).text("Horizontal bias"))
+            .changed();
 
-        // changed |= ui
-        //     .add(egui::Slider::new(&mut settings.vert_seed, 0..=255).text("Vertical Seed"))
-        //     .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut settings.vert_bias, 0.0..=1.0).text("Vertical bias"))
+            .changed();
+
+        // Changing the length changes how many selectors `fit_selectors` generates, which
+        // regenerates them
+        let mut repeat = settings.selector_len.is_some();
+        if ui.checkbox(&mut repeat, "Repeat the selectors").changed() {
+            let (min, max) = SELECTOR_LEN_RANGE.into_inner();
+            let len = settings.horz_selectors.len().clamp(min, max);
+            settings.selector_len = repeat.then_some(len);
+        }
+        if let Some(len) = &mut settings.selector_len {
+            ui.add(egui::Slider::new(len, SELECTOR_LEN_RANGE).text("Selectors per axis"));
+        }
 
-        // if changed {
-        //     let mut rng: Pcg64 = Seeder::from(settings.horz_seed).make_rng();
-        //     rng.fill(&mut settings.horz_selectors[..]);
+        ui.horizontal(|ui| {
+            changed |= ui.text_edit_singleline(&mut settings.horz_text).changed();
+            ui.label("Horizontal text (replaces the seed)");
+        });
+        ui.horizontal(|ui| {
+            changed |= ui.text_edit_singleline(&mut settings.vert_text).changed();
+            ui.label("Vertical text (replaces the seed)");
+        });
 
-        //     let mut rng: Pcg64 = Seeder::from(settings.vert_seed).make_rng();
-        //     rng.fill(&mut settings.vert_selectors[..]);
-        // }
+        egui::ComboBox::from_label("Vertical selectors")
+            .selected_text(settings.vert_derivation.label())
+            .show_ui(ui, |ui| {
+                for derivation in [
+                    VertDerivation::Seed,
+                    VertDerivation::Reverse,
+                    VertDerivation::Rotate(1),
+                ] {
+                    let selected = std::mem::discriminant(&settings.vert_derivation)
+                        == std::mem::discriminant(&derivation);
+                    if ui.selectable_label(selected, derivation.label()).clicked() && !selected {
+                        settings.vert_derivation = derivation;
+                        changed = true;
+                    }
+                }
+            });
+        if let VertDerivation::Rotate(ref mut k) = settings.vert_derivation {
+            let max_rotation = settings.horz_selectors.len().saturating_sub(1);
+            changed |= ui
+                .add(egui::Slider::new(k, 0..=max_rotation).text("Rotate by"))
+                .changed();
+        }
+        ui.horizontal(|ui| {
+            changed |= ui
+                .checkbox(&mut settings.mirror_x, "Mirror left to right")
+                .changed();
+            changed |= ui
+                .checkbox(&mut settings.mirror_y, "Mirror top to bottom")
+                .changed();
+        });
+
+        ui.add(egui::Slider::new(min_density, 0.0..=1.0).text("Min density"));
+        ui.add(egui::Slider::new(max_density, 0.0..=1.0).text("Max density"));
+        if ui.button("Randomize within density band").clicked() {
+            let mut rng = nannou::rand::thread_rng();
+            let (min, max) = (*min_density, *max_density);
+            let (len, bias) = (settings.horz_selectors.len(), settings.horz_bias);
+            settings.horz_seed = seed_in_density_band(&mut rng, len, bias, min, max);
+            if settings.vert_derivation == VertDerivation::Seed {
+                let (len, bias) = (settings.vert_selectors.len(), settings.vert_bias);
+                settings.vert_seed = seed_in_density_band(&mut rng, len, bias, min, max);
+            }
+            regenerate = true;
+        }
+        let mut surprise = false;
+        ui.horizontal(|ui| {
+            surprise = ui.button("Surprise me").clicked();
+            let seed_changed = ui.add(egui::DragValue::new(surprise_seed)).changed();
+            ui.label("Surprise seed");
+            if ui.button("Restart").clicked() || seed_changed {
+                *surprise_rng = Pcg64::seed_from_u64(*surprise_seed);
+            }
+        });
+        if surprise {
+            let rng = &mut *surprise_rng;
+            let spacing = rng.gen_range(10.0..=100.0);
+            settings.horz_spacing = spacing;
+            settings.vert_spacing = spacing;
+            settings.horz_seed = rng.gen_range(0..=MAX_SURPRISE_SEED);
+            settings.vert_seed = rng.gen_range(0..=MAX_SURPRISE_SEED);
+            settings.diag_seed = rng.gen_range(0..=MAX_SURPRISE_SEED);
+            for color in [
+                &mut settings.horz_color,
+                &mut settings.vert_color,
+                &mut settings.border_color,
+                &mut settings.caption_color,
+            ] {
+                *color = Rgb::new(rng.gen(), rng.gen(), rng.gen());
+            }
+            regenerate = true;
+        }
+
+        // Hand-edited selectors survive seed changes until explicitly regenerated
+        if settings.edited {
+            ui.horizontal(|ui| {
+                ui.label("Selectors edited by hand");
+                regenerate |= ui.button("Regenerate from seed").clicked();
+            });
+        }
+        if regenerate || (changed && !settings.edited) {
+            settings.regenerate_selectors();
+        }
+
+        egui::CollapsingHeader::new("Edit selectors").show(ui, |ui| {
+            for axis in [Axis::Horizontal, Axis::Vertical] {
+                selector_toggles(ui, settings, axis, cursor);
+            }
+        });
+
+        egui::CollapsingHeader::new("Selector bits").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(bits_shown).clamp_range(BITS_SHOWN_RANGE));
+                ui.label("bits shown");
+            });
+            for (axis, input) in [Axis::Horizontal, Axis::Vertical]
+                .into_iter()
+                .zip(bits_input)
+            {
+                if let Err(e) = selector_bits(ui, settings, axis, *bits_shown, input) {
+                    *error = Some(e);
+                }
+            }
+        });
+
+        ui.checkbox(&mut settings.brick, "Stagger rows like bricks");
+        ui.checkbox(&mut settings.tileable, "Export tileable patterns");
+        ui.label("Arrow keys pick a selector, Space toggles it, Esc stops");
+
+        ui.horizontal(|ui| {
+            color_picker(ui, &mut settings.background_color);
+            ui.label("Background color (the bottom layer's is used)");
+        });
+        invert_colors = ui.button("Invert colors").clicked();
+        ui.horizontal(|ui| {
+            color_picker(ui, &mut settings.horz_color);
+            ui.label("Horizontal line color");
+        });
+        ui.horizontal(|ui| {
+            color_picker(ui, &mut settings.vert_color);
+            ui.label("Vertical line color");
+        });
+        if settings.grid == Grid::Square {
+            ui.checkbox(&mut settings.color_by_loop, "Color each loop differently");
+        }
+        ui.checkbox(&mut settings.gradient, "Fade lines between two colors");
+        if settings.gradient {
+            ui.horizontal(|ui| {
+                color_picker(ui, &mut settings.color_start);
+                ui.label("Start color");
+                color_picker(ui, &mut settings.color_end);
+                ui.label("End color");
+            });
+        }
+        ui.add(egui::Slider::new(&mut settings.line_weight, 1.0..=10.0).text("Line weight"));
+        ui.checkbox(&mut settings.antialias, "Anti-alias exported lines");
+        ui.checkbox(
+            &mut settings.round_caps,
+            "Round the ends of exported stitches",
+        );
+        ui.add(egui::Slider::new(&mut settings.opacity, 0.0..=1.0).text("Opacity"));
+        ui.checkbox(
+            &mut settings.dashed,
+            "Stitch dashes (otherwise draw solid lines)",
+        );
+        if settings.dashed {
+            ui.add(egui::Slider::new(&mut settings.dash_ratio, 0.05..=1.0).text("Dash ratio"));
+            ui.add(
+                egui::Slider::new(&mut settings.dashes_per_cell, 1..=MAX_DASHES_PER_CELL)
+                    .text("Dashes per cell"),
+            );
+        }
+
+        ui.separator();
+        ui.checkbox(&mut settings.draw_border, "Draw border");
+        ui.horizontal(|ui| {
+            color_picker(ui, &mut settings.border_color);
+            ui.label("Border color");
+        });
+        ui.add(egui::Slider::new(&mut settings.border_width, 1.0..=50.0).text("Border width"));
+
+        ui.separator();
+        ui.checkbox(&mut settings.draw_caption, "Caption exported image");
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut settings.caption_text);
+            ui.label("Caption");
+        });
+        egui::ComboBox::from_label("Caption corner")
+            .selected_text(settings.caption_corner.label())
+            .show_ui(ui, |ui| {
+                for corner in Corner::ALL {
+                    ui.selectable_value(&mut settings.caption_corner, corner, corner.label());
+                }
+            });
+        ui.add(egui::Slider::new(&mut settings.caption_size, 8.0..=128.0).text("Caption size"));
+        ui.horizontal(|ui| {
+            color_picker(ui, &mut settings.caption_color);
+            ui.label("Caption color");
+        });
+
+        egui::ComboBox::from_label("Lock export to grid")
+            .selected_text(settings.grid_lock.label())
+            .show_ui(ui, |ui| {
+                for lock in GridLock::ALL {
+                    ui.selectable_value(&mut settings.grid_lock, lock, lock.label());
+                }
+            });
+        ui.checkbox(
+            &mut settings.draw_legend,
+            "Add legend strip to exported image",
+        );
+        ui.checkbox(custom_export_size, "Custom export size");
+        if *custom_export_size {
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(export_width).clamp_range(1..=MAX_EXPORT_SIZE));
+                ui.label("x");
+                ui.add(egui::DragValue::new(export_height).clamp_range(1..=MAX_EXPORT_SIZE));
+            });
+        }
+        let (image_width, image_height) = settings.export_size(*export_width, *export_height);
+        ui.label(format!("Export size: {image_width} x {image_height}"));
+        egui::ComboBox::from_label("Letterbox PNG to")
+            .selected_text(settings.aspect_ratio.label())
+            .show_ui(ui, |ui| {
+                for ratio in AspectRatio::ALL {
+                    ui.selectable_value(&mut settings.aspect_ratio, ratio, ratio.label());
+                }
+            });
+        // Only PNGs are letterboxed, and only they say what size to print them at
+        let (png_width, png_height) = settings.aspect_ratio.letterbox(image_width, image_height);
+        if (png_width, png_height) != (image_width, image_height) {
+            ui.label(format!("PNG size: {png_width} x {png_height}"));
+        }
+        egui::ComboBox::from_label("Save PNG as")
+            .selected_text(settings.png_color.label())
+            .show_ui(ui, |ui| {
+                for png_color in PngColor::ALL {
+                    ui.selectable_value(&mut settings.png_color, png_color, png_color.label());
+                }
+            });
+        if settings.png_color != PngColor::Rgba && !settings.is_gray() {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                "The colors will be saved as how bright they are",
+            );
+        }
+        ui.add(egui::Slider::new(&mut settings.dpi, 72..=600).text("DPI"));
+        let dpi = settings.dpi as f32;
+        ui.label(format!(
+            "Print size: {:.2} x {:.2} in",
+            png_width as f32 / dpi,
+            png_height as f32 / dpi
+        ));
+        let (pattern_width, pattern_height) = settings.pattern_size(*export_width, *export_height);
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(px_per_mm)
+                    .speed(0.1)
+                    .clamp_range(0.1..=100.0),
+            );
+            ui.label("Pixels per mm");
+        });
+        let thread_length =
+            total_thread_length(settings, pattern_width, pattern_height, *px_per_mm);
+        ui.label(format!("Thread needed: {:.0} mm", thread_length));
+
+        ui.checkbox(&mut settings.auto_name, "Name exports from settings");
+        if settings.auto_name {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut settings.file_name_template);
+                ui.label("File name");
+            });
+            ui.label(settings.file_name());
+        }
+
+        if saving.is_some() {
+            // Only one PNG is saved at a time
+            ui.add(
+                egui::ProgressBar::new(0.0)
+                    .animate(true)
+                    .text("Saving PNG..."),
+            );
+        } else if ui.button("Save as PNG").clicked() {
+            save_png = true;
+        }
+        ui.checkbox(show_preview, "Preview the PNG");
+        if *show_preview {
+            egui::ComboBox::from_label("Preview as")
+                .selected_text(preview_filter.label())
+                .show_ui(ui, |ui| {
+                    for filter in ContrastFilter::ALL {
+                        ui.selectable_value(preview_filter, filter, filter.label());
+                    }
+                });
+            if !settings.low_contrast_colors(*preview_filter).is_empty() {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    "Some stitches are hard to tell from the background",
+                );
+            }
+            match preview {
+                Some(preview) => {
+                    ui.image(preview.texture, preview.size);
+                }
+                None => {
+                    ui.label("Rendering the preview...");
+                }
+            }
+        }
+
+        if ui.button("Save as SVG").clicked() {
+            let mut dialog = rfd::FileDialog::new().add_filter("SVG", &["svg"]);
+            if settings.auto_name {
+                let name = Path::new(&settings.file_name()).with_extension("svg");
+                dialog = dialog.set_file_name(name.to_string_lossy());
+            }
+            if let Some(path) = dialog.save_file() {
+                let (width, height) = settings.pattern_size(*export_width, *export_height);
+                if let Err(e) = write_svg(&path, settings, width, height) {
+                    *error = Some(format!("Failed to create SVG: {e}"));
+                }
+            }
+        }
+
+        if ui.button("Save as WebP").clicked() {
+            let mut dialog = rfd::FileDialog::new().add_filter("WebP", &["webp"]);
+            if settings.auto_name {
+                let name = Path::new(&settings.file_name()).with_extension("webp");
+                dialog = dialog.set_file_name(name.to_string_lossy());
+            }
+            if let Some(path) = dialog.save_file() {
+                let (width, height) = settings.export_size(*export_width, *export_height);
+                if let Err(e) = write_webp(&path, settings, width, height) {
+                    *error = Some(format!("Failed to create WebP: {e}"));
+                }
+            }
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Save as JPEG").clicked() {
+                let mut dialog = rfd::FileDialog::new().add_filter("JPEG", &["jpg", "jpeg"]);
+                if settings.auto_name {
+                    let name = Path::new(&settings.file_name()).with_extension("jpg");
+                    dialog = dialog.set_file_name(name.to_string_lossy());
+                }
+                if let Some(path) = dialog.save_file() {
+                    let (width, height) = settings.export_size(*export_width, *export_height);
+                    if let Err(e) = write_jpeg(&path, settings, width, height, *jpeg_quality) {
+                        *error = Some(format!("Failed to create JPEG: {e}"));
+                    }
+                }
+            }
+            ui.add(egui::Slider::new(jpeg_quality, 1..=100).text("Quality"));
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Export GIF").clicked() {
+                let mut dialog = rfd::FileDialog::new().add_filter("GIF", &["gif"]);
+                if settings.auto_name {
+                    let name = Path::new(&settings.file_name()).with_extension("gif");
+                    dialog = dialog.set_file_name(name.to_string_lossy());
+                }
+                if let Some(path) = dialog.save_file() {
+                    let (width, height) = settings.export_size(*export_width, *export_height);
+                    let (settings, axis, fps) = (settings.clone(), *gif_axis, *gif_fps);
+                    // 256 frames take a while, so keep the window responsive
+                    exports.push(begin_export("GIF", path, move |path| {
+                        write_seed_sweep_gif(path, &settings, width, height, axis, fps)
+                    }));
+                }
+            }
+            egui::ComboBox::from_id_source("gif_axis")
+                .selected_text(match gif_axis {
+                    Axis::Horizontal => "Sweep horizontal seed",
+                    Axis::Vertical => "Sweep vertical seed",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(gif_axis, Axis::Horizontal, "Sweep horizontal seed");
+                    ui.selectable_value(gif_axis, Axis::Vertical, "Sweep vertical seed");
+                });
+            ui.add(egui::Slider::new(gif_fps, 1..=50).text("FPS"));
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Contact sheet").clicked() {
+                let mut dialog = rfd::FileDialog::new().add_filter("PNG", &["png"]);
+                if settings.auto_name {
+                    let stem = Path::new(&settings.file_name()).with_extension("");
+                    dialog = dialog.set_file_name(format!("{}_sheet.png", stem.display()));
+                }
+                if let Some(path) = dialog.save_file() {
+                    let (settings, count, tile_size) =
+                        (settings.clone(), *sheet_count, *sheet_tile_size);
+                    // Like the GIF, a big sheet renders a lot of patterns
+                    exports.push(begin_export("contact sheet", path, move |path| {
+                        write_contact_sheet(path, &settings, count, tile_size)
+                    }));
+                }
+            }
+            ui.add(egui::DragValue::new(sheet_count).clamp_range(1..=16));
+            ui.label("tiles per side");
+            ui.add(egui::DragValue::new(sheet_tile_size).clamp_range(16..=1024));
+            ui.label("pixels per tile");
+        });
+
+        if batch.is_some() {
+            let progress = *batch_done as f32 / (*batch_total).max(1) as f32;
+            ui.add(
+                egui::ProgressBar::new(progress)
+                    .animate(true)
+                    .text(format!("Rendered {} of {}", *batch_done, *batch_total)),
+            );
+        } else if ui
+            .button("Batch from file")
+            .on_hover_text(
+                "Render a PNG for each horz_seed,vert_seed,spacing line of a CSV file, with \
+                 the rest of the settings from this layer",
+            )
+            .clicked()
+        {
+            let dialog = rfd::FileDialog::new().add_filter("CSV", &["csv"]);
+            if let Some(path) = dialog.pick_file() {
+                match fs::read_to_string(&path) {
+                    Ok(text) => {
+                        if let Some(out_dir) = rfd::FileDialog::new().pick_folder() {
+                            let rows = parse_batch_csv(&text);
+                            let export_size = (*export_width, *export_height);
+                            *batch_total = rows.len();
+                            *batch = Some(begin_batch(rows, settings, export_size, &out_dir));
+                            *batch_done = 0;
+                            batch_failures.clear();
+                        }
+                    }
+                    Err(e) => *error = Some(format!("Failed to read {}: {e}", path.display())),
+                }
+            }
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Export CSV").clicked() {
+                let mut dialog = rfd::FileDialog::new().add_filter("CSV", &["csv"]);
+                if settings.auto_name {
+                    let name = Path::new(&settings.file_name()).with_extension("csv");
+                    dialog = dialog.set_file_name(name.to_string_lossy());
+                }
+                if let Some(path) = dialog.save_file() {
+                    let (cols, rows) = if *custom_chart_size {
+                        (*chart_cols, *chart_rows)
+                    } else {
+                        chart_size(settings, *export_width, *export_height)
+                    };
+                    if let Err(e) = write_csv(&path, settings, cols, rows) {
+                        *error = Some(format!("Failed to create CSV: {e}"));
+                    }
+                }
+            }
+            ui.checkbox(custom_chart_size, "Custom size");
+            if *custom_chart_size {
+                ui.add(egui::DragValue::new(chart_cols).clamp_range(1..=1024));
+                ui.label("x");
+                ui.add(egui::DragValue::new(chart_rows).clamp_range(1..=1024));
+                ui.label("cells");
+            }
+        });
+
+        if ui.button("Load PNG or SVG").clicked() {
+            let dialog = rfd::FileDialog::new().add_filter("Image", &["png", "svg"]);
+            if let Some(path) = dialog.pick_file() {
+                // SVGs only hold the first layer
+                let loaded = if path.extension().is_some_and(|ext| ext == "svg") {
+                    load_settings_from_svg(&path).map(|settings| vec![settings])
+                } else {
+                    load_layers_from_png(&path)
+                };
+                match loaded {
+                    Ok(loaded) => {
+                        *notice = (!loaded.iter().all(selectors_match_seed)).then(|| {
+                            format!(
+                                "The selectors saved in {} don't match its seeds, so they were \
+                                 edited by hand",
+                                path.display()
+                            )
+                        });
+                        loaded_layers = Some(loaded);
+                        *error = None;
+                    }
+                    Err(e) => *error = Some(format!("Failed to load {}: {e}", path.display())),
+                }
+            }
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Export settings").clicked() {
+                let mut dialog = rfd::FileDialog::new().add_filter("Hitomezashi", &["hito"]);
+                if settings.auto_name {
+                    let name = Path::new(&settings.file_name()).with_extension("hito");
+                    dialog = dialog.set_file_name(name.to_string_lossy());
+                }
+                if let Some(path) = dialog.save_file() {
+                    if let Err(e) = write_hito(&path, settings) {
+                        *error = Some(format!("Failed to export {}: {e}", path.display()));
+                    }
+                }
+            }
+            if ui.button("Import settings").clicked() {
+                let dialog = rfd::FileDialog::new().add_filter("Hitomezashi", &["hito"]);
+                if let Some(path) = dialog.pick_file() {
+                    match load_hito(&path) {
+                        Ok(loaded) => {
+                            *settings = loaded;
+                            *error = None;
+                        }
+                        Err(e) => {
+                            *error = Some(format!("Failed to import {}: {e}", path.display()))
+                        }
+                    }
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Copy settings").clicked() {
+                let json = settings.to_json();
+                if let Err(e) = open_clipboard(clipboard).and_then(|c| c.set_text(json)) {
+                    *error = Some(format!("Failed to copy settings: {e}"));
+                }
+            }
+            if ui.button("Paste settings").clicked() {
+                let pasted: Result<_, Box<dyn Error>> = open_clipboard(clipboard)
+                    .and_then(|c| c.get_text())
+                    .map_err(Box::from)
+                    .and_then(|json| Ok(RectSettings::from_json(&json)?));
+                match pasted {
+                    Ok(pasted) => {
+                        *settings = pasted;
+                        *error = None;
+                    }
+                    Err(e) => *error = Some(format!("Failed to paste settings: {e}")),
+                }
+            }
+            if ui.button("Copy as ASCII").clicked() {
+                // One character cell per grid cell of the exported pattern
+                let (cols, rows) = chart_size(settings, *export_width, *export_height);
+                let ascii = pattern_to_ascii(settings, cols, rows);
+                if let Err(e) = open_clipboard(clipboard).and_then(|c| c.set_text(ascii)) {
+                    *error = Some(format!("Failed to copy the pattern: {e}"));
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            copy_image = ui.button("Copy image").clicked();
+            ui.add(egui::DragValue::new(copy_size).clamp_range(16..=MAX_EXPORT_SIZE));
+            ui.label("pixels at most on each side");
+        });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(preset_name);
+            if ui.button("Save preset").clicked() && !preset_name.is_empty() {
+                presets.push((preset_name.clone(), settings.clone()));
+                preset_name.clear();
+                if let Err(e) = save_presets(presets) {
+                    *error = Some(format!("Failed to save presets: {e}"));
+                }
+            }
+        });
+        egui::ComboBox::from_id_source("presets")
+            .selected_text("Load preset")
+            .show_ui(ui, |ui| {
+                for (name, preset) in presets.iter() {
+                    if ui.selectable_label(false, name).clicked() {
+                        *settings = preset.clone();
+                    }
+                }
+            });
+    });
+
+    egui::Window::new("Statistics")
+        .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+        .resizable(false)
+        .show(&ctx, |ui| {
+            let pattern = Pattern::new(settings, bounds.w() as u32, bounds.h() as u32);
+            // The part of the pattern on screen, undoing the view's zoom and pan
+            let visible = Rect::from_corners(
+                (bounds.bottom_left() - pan) / zoom,
+                (bounds.top_right() - pan) / zoom,
+            )
+            .overlap(pattern_bounds(settings, bounds));
+            for (axis, name) in [
+                (Axis::Horizontal, "Horizontal"),
+                (Axis::Vertical, "Vertical"),
+            ] {
+                let lines = visible.map_or_else(Vec::new, |visible| {
+                    // In image coordinates, from the top left corner of the window
+                    let area = [
+                        [visible.left() - bounds.left(), bounds.top() - visible.top()],
+                        [
+                            visible.right() - bounds.left(),
+                            bounds.top() - visible.bottom(),
+                        ],
+                    ];
+                    pattern.line_numbers_in(axis, area)
+                });
+                let on = lines
+                    .iter()
+                    .filter(|&&line| selector_at(settings.selectors(axis), line))
+                    .count();
+                let off = lines.len() - on;
+                ui.label(format!("{name} lines: {} ({on} on, {off} off)", on + off));
+            }
+            // The loops are counted in the exported pattern, like the chart
+            if settings.grid == Grid::Square {
+                let (cols, rows) = chart_size(settings, *export_width, *export_height);
+                if cols * rows <= MAX_LOOP_COUNT_CELLS {
+                    let loops = count_loops(settings, cols, rows);
+                    ui.label(format!("Loops: {loops}")).on_hover_text(
+                        "Pieces that reach the edge of the pattern count as loops too",
+                    );
+                }
+            }
+        });
+
+    egui::TopBottomPanel::bottom("status").show(&ctx, |ui| {
+        ui.horizontal(|ui| {
+            // Any edits to the text are thrown away, but it can still be selected
+            let mut query = format!("/pattern.png?{}", settings_to_query(settings));
+            ui.add(egui::TextEdit::singleline(&mut query).desired_width(400.0));
+            if ui.button("Copy URL").clicked() {
+                if let Err(e) = open_clipboard(clipboard).and_then(|c| c.set_text(query)) {
+                    *error = Some(format!("Failed to copy the URL: {e}"));
+                }
+            }
+        });
+    });
+
+    // Highlight the cell under the mouse, and say which selectors decide whether each of its
+    // edges is stitched
+    *hovered_cell = None;
+    let mouse = app.mouse.position();
+    // Undo the live view's zoom and pan to find the point in the pattern
+    let point = (mouse - pan) / zoom;
+    let inside = pattern_bounds(settings, bounds);
+    if settings.grid == Grid::Square
+        && bounds.contains(mouse)
+        && inside.contains(point)
+        && !ctx.is_pointer_over_area()
+    {
+        let cell = GridCell::at(settings, inside, point);
+        egui::show_tooltip_at_pointer(&ctx, egui::Id::new("cell"), |ui| {
+            for (edge, axis, line) in [
+                ("Top", Axis::Horizontal, cell.row),
+                ("Bottom", Axis::Horizontal, cell.row + 1),
+                ("Left", Axis::Vertical, cell.col),
+                ("Right", Axis::Vertical, cell.col + 1),
+            ] {
+                let selectors = settings.selectors(axis);
+                let index = line.rem_euclid(selectors.len().max(1) as i64);
+                let name = match axis {
+                    Axis::Horizontal => "horizontal",
+                    Axis::Vertical => "vertical",
+                };
+                let value = selector_at(selectors, line);
+                ui.label(format!("{edge}: {name} selector {index} is {value}"));
+            }
+        });
+        *hovered_cell = Some(cell);
+    }
+
+    if let Some(mut loaded) = loaded_layers {
+        // Put the window back to the size the pattern was saved at, so it shows the same
+        // lines. The size isn't kept in the layers, so it isn't saved with a later export
+        // unless the window is still that size.
+        let window_size = loaded[0].window_width.zip(loaded[0].window_height);
+        for layer in &mut loaded {
+            (layer.window_width, layer.window_height) = (None, None);
+        }
+        if let Some((width, height)) = window_size {
+            app.main_window()
+                .set_inner_size_points(width as f32, height as f32);
+        }
+        *layers = loaded;
+        *active_layer = 0;
+    }
+    if invert_colors {
+        for layer in layers.iter_mut() {
+            layer.invert_colors();
+        }
+    }
+    if save_png {
+        let window_size = (bounds.w() as u32, bounds.h() as u32);
+        begin_save(layers, (*export_width, *export_height), window_size, saving);
+    }
+    if copy_image {
+        let export_size = (*export_width, *export_height);
+        let (buffer, width, height) = render_png_image(layers, export_size);
+        let (buffer, width, height) = downscale_rgba(&buffer, width, height, *copy_size);
+        let image = arboard::ImageData {
+            width: width as usize,
+            height: height as usize,
+            bytes: buffer.into(),
+        };
+        if let Err(e) = open_clipboard(clipboard).and_then(|c| c.set_image(image)) {
+            *error = Some(format!("Failed to copy the image: {e}"));
+        }
+    }
+
+    if let Some(message) = error.clone() {
+        egui::Window::new("Error")
+            .collapsible(false)
+            .show(&ctx, |ui| {
+                ui.label(message);
+                if ui.button("OK").clicked() {
+                    *error = None;
+                }
+            });
+    }
+
+    if let Some(message) = notice.clone() {
+        egui::Window::new("Notice")
+            .collapsible(false)
+            .show(&ctx, |ui| {
+                ui.label(message);
+                if ui.button("OK").clicked() {
+                    *notice = None;
+                }
+            });
+    }
+
+    // The selectors may change without any window event, e.g. when they are toggled
+    // programmatically, so ask for a redraw rather than waiting for the next event
+    let mut dirty = false;
+    for layer in layers.iter_mut() {
+        dirty |= std::mem::take(&mut layer.dirty);
+    }
+    if dirty {
+        app.main_window().winit_window().request_redraw();
+    }
+}
+
+/// Show an egui color picker button that edits `color` in place
+fn color_picker(ui: &mut egui::Ui, color: &mut Rgb) -> egui::Response {
+    let mut rgb = [color.red, color.green, color.blue];
+    let response = ui.color_edit_button_srgb(&mut rgb);
+    if response.changed() {
+        *color = Rgb::new(rgb[0], rgb[1], rgb[2]);
+    }
+    response
+}
+
+/// Where the presets are kept, in the user's config directory
+fn presets_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("hitomezashi").join("presets.json"))
+}
+
+/// The saved presets, or none if the presets file is missing or can't be read
+fn load_presets() -> Vec<(String, RectSettings)> {
+    let saved: Option<Vec<(String, serde_json::Value)>> = presets_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok());
+    // Load each preset like any other saved settings, so they are checked and their
+    // selectors are regenerated the same way
+    saved
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| Ok((name, RectSettings::from_value(value)?)))
+        .collect::<Result<_, LoadError>>()
+        .unwrap_or_default()
+}
+
+/// Write `presets` to `presets_path`, replacing whatever was there
+fn save_presets(presets: &[(String, RectSettings)]) -> Result<(), Box<dyn Error>> {
+    let path = presets_path().ok_or("There is no config directory to save presets in")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(presets)?)?;
+    Ok(())
+}
+
+/// The system clipboard, opening it if this is the first time it is needed. It is kept
+/// open afterwards because on some platforms copied text only stays on the clipboard for
+/// as long as the clipboard is open.
+fn open_clipboard(
+    clipboard: &mut Option<arboard::Clipboard>,
+) -> Result<&mut arboard::Clipboard, arboard::Error> {
+    if clipboard.is_none() {
+        *clipboard = Some(arboard::Clipboard::new()?);
+    }
+    Ok(clipboard.as_mut().expect("The clipboard was just opened"))
+}
+
+/// `color` as a nannou color, for drawing in the window
+fn to_srgb(color: Rgb) -> Srgb<u8> {
+    srgb(color.red, color.green, color.blue)
+}
+
+/// The color of the stitches drawn with `style`, `t` of the way along their line (see
+/// `LineStyle::color_at`), as a nannou color. The opacity is the alpha, so the window blends
+/// them like the PNG does.
+fn line_color(style: LineStyle, t: f32) -> Srgba<u8> {
+    let alpha = (style.opacity * 255.0).round() as u8;
+    let color = style.color_at(t);
+    srgba(color.red, color.green, color.blue, alpha)
+}
+
+/// The color of `stitch` drawn with `style` in a pattern that fills `bounds`, taken from
+/// the gradient at its middle
+fn stitch_color(style: LineStyle, stitch: &Stitch, bounds: Rect) -> Srgba<u8> {
+    let middle = [
+        (stitch.start[0] + stitch.end[0]) / 2.0,
+        (stitch.start[1] + stitch.end[1]) / 2.0,
+    ];
+    let t = gradient_position(stitch.axis, middle, bounds.w(), bounds.h());
+    line_color(style, t)
+}
+
+/// How many columns and rows of cells the pattern exported at `export_width` x
+/// `export_height` has
+fn chart_size(settings: &RectSettings, export_width: u32, export_height: u32) -> (usize, usize) {
+    let (width, height) = settings.pattern_size(export_width, export_height);
+    let cols = (width as f32 / settings.spacing(Axis::Vertical)) as usize;
+    let rows = (height as f32 / settings.spacing(Axis::Horizontal)) as usize;
+    (cols, rows)
+}
+
+/// Show one checkbox per selector on `axis` (up to `MAX_SELECTOR_TOGGLES`), flipping the
+/// selector by hand when clicked. The selector under the keyboard cursor is outlined.
+fn selector_toggles(
+    ui: &mut egui::Ui,
+    settings: &mut RectSettings,
+    axis: Axis,
+    cursor: Option<SelectorCursor>,
+) {
+    let count = settings.selectors(axis).len().min(MAX_SELECTOR_TOGGLES);
+    ui.label(match axis {
+        Axis::Horizontal => "Horizontal selectors",
+        Axis::Vertical => "Vertical selectors",
+    });
+    ui.horizontal_wrapped(|ui| {
+        for index in 0..count {
+            let mut value = settings.selectors(axis)[index];
+            let focused = cursor == Some(SelectorCursor { axis, index });
+            let stroke = if focused {
+                egui::Stroke::new(2.0, egui::Color32::from_rgb(255, 140, 0))
+            } else {
+                egui::Stroke::none()
+            };
+            egui::Frame::none().stroke(stroke).show(ui, |ui| {
+                if ui.checkbox(&mut value, "").changed() {
+                    settings.toggle_selector(axis, index);
+                }
+            });
+        }
+    });
+}
+
+/// Show the selectors on `axis` as a string of bits (the first `shown` of them), with a
+/// field to type in bits that replace them by hand. Returns why the typed bits couldn't be
+/// used, if they couldn't.
+fn selector_bits(
+    ui: &mut egui::Ui,
+    settings: &mut RectSettings,
+    axis: Axis,
+    shown: usize,
+    input: &mut String,
+) -> Result<(), String> {
+    let name = match axis {
+        Axis::Horizontal => "horizontal",
+        Axis::Vertical => "vertical",
+    };
+    let bits = selectors_to_bits(settings.selectors(axis));
+    ui.label(format!("{} {name} selectors", bits.len()));
+    if bits.len() > shown {
+        ui.monospace(format!(
+            "{}... ({} more)",
+            &bits[..shown],
+            bits.len() - shown
+        ));
+    } else {
+        ui.monospace(&bits);
+    }
+
+    let mut result = Ok(());
+    ui.horizontal(|ui| {
+        ui.add(
+            egui::TextEdit::singleline(input)
+                .hint_text("0s and 1s")
+                .desired_width(200.0),
+        );
+        if ui.button("Copy current").clicked() {
+            *input = bits;
+        }
+        if ui.button("Set").clicked() {
+            result = selectors_from_bits(input)
+                .map(|selectors| settings.set_selectors(axis, selectors))
+                .map_err(|e| format!("Failed to set the {name} selectors: {e}"));
+        }
     });
+    result
 }
 
 fn view(app: &App, model: &Model, frame: Frame) {
     // Prepare to draw.
     let draw = app.draw();
 
-    // Set the background color
-    draw.background().color(WHITE);
+    // Set the background color, which like the export's comes from the bottom layer
+    draw.background()
+        .color(to_srgb(model.layers[0].background_color));
 
-    // Draw the pattern as specified by the model settings
-    model.settings.display(&draw, app.window_rect());
+    // Draw the pattern of each layer as specified by its settings, zoomed and panned, with
+    // later layers on top
+    let view = draw.translate(model.pan.extend(0.0)).scale(model.zoom);
+    for layer in &model.layers {
+        draw_pattern(&view, app.window_rect(), layer);
+    }
+
+    // Show which lines the keyboard cursor's selector controls
+    let settings = &model.layers[model.active_layer];
+    if let (Some(cursor), Grid::Square) = (model.cursor, settings.grid) {
+        let inside = pattern_bounds(settings, app.window_rect());
+        draw_cursor_highlight(&view, inside, settings, cursor);
+    }
+
+    // Show which cell the mouse is over
+    if let Some(cell) = model.hovered_cell {
+        let rect = cell.rect(settings, pattern_bounds(settings, app.window_rect()));
+        view.rect()
+            .xy(rect.xy())
+            .wh(rect.wh())
+            .color(rgba(0.0, 0.5, 1.0, 0.25));
+    }
 
     // Write to the window frame.
     draw.to_frame(app, &frame).unwrap();
     model.egui.draw_to_frame(&frame).unwrap();
 }
 
-#[allow(dead_code)]
-fn draw_vertical_lines(draw: &Draw, bounds: Rect, spacing: f32) {
-    let mut current_x_pos = bounds.left() + spacing;
-
-    while current_x_pos < bounds.right() {
-        // Draw the line from top to bottom
-        draw.line()
-            .start(pt2(current_x_pos, bounds.top()))
-            .end(pt2(current_x_pos, bounds.bottom()))
-            .weight(3.0);
+/// Draw the hitomezashi pattern described by `settings` within `bounds`
+fn draw_pattern(draw: &Draw, bounds: Rect, settings: &RectSettings) {
+    match settings.grid {
+        Grid::Square => draw_hito_square(draw, bounds, settings),
+        Grid::Triangular => draw_hito_triangular(draw, bounds, settings),
+        Grid::Polar => draw_hito_polar(draw, bounds, settings),
+    }
 
-        // Update drawing position
-        current_x_pos += spacing;
+    if settings.draw_border {
+        draw_border(draw, bounds, settings.border_color, settings.border_width);
     }
 }
 
-#[allow(dead_code)]
-fn draw_horizontal_lines(draw: &Draw, bounds: Rect, spacing: f32) {
-    // Start at the top and go down
-    let mut current_y_pos = bounds.top() + spacing;
-
-    while current_y_pos > bounds.bottom() {
-        // Draw the line from left to right
-        draw.line()
-            .start(pt2(bounds.left(), current_y_pos))
-            .end(pt2(bounds.right(), current_y_pos))
-            .weight(3.0);
+/// The part of `bounds` inside the margin of the pattern drawn in them, which its grid is
+/// laid out in (see `RectSettings::margin`)
+fn pattern_bounds(settings: &RectSettings, bounds: Rect) -> Rect {
+    bounds.pad(Pattern::new(settings, bounds.w() as u32, bounds.h() as u32).margin())
+}
 
-        // Move drawing position down
-        current_y_pos -= spacing;
-    }
+/// Draw a solid rectangle outline of `weight` thickness just inside the edge of `bounds`,
+/// so that the outermost pixels are all `color`.
+fn draw_border(draw: &Draw, bounds: Rect, color: Rgb, weight: f32) {
+    // The stroke is centered on the rectangle's edge, so pull the edge in by half the
+    // weight to keep the whole outline on screen
+    draw.rect()
+        .xy(bounds.xy())
+        .wh(bounds.wh() - vec2(weight, weight))
+        .no_fill()
+        .stroke(to_srgb(color))
+        .stroke_weight(weight);
 }
 
-/// Draws a dashed line from `start` to `end`. The length of each dash is the same as the
-/// length of each gap.
-fn draw_dashed_line(draw: &Draw, start: Point2, end: Point2, dash_length: f32) {
-    // Create a vector poiting from `start` to `end`, of lengh `dash_length`
-    let draw_direction = (end - start).normalize() * dash_length;
+/// Highlight every line whose start is chosen by the selector under `cursor`. Because the
+/// selectors repeat across the window, that is every `len`th line on the cursor's axis.
+fn draw_cursor_highlight(
+    draw: &Draw,
+    bounds: Rect,
+    settings: &RectSettings,
+    cursor: SelectorCursor,
+) {
+    let len = settings.selectors(cursor.axis).len();
+    if len == 0 {
+        return;
+    }
+    let gaps = settings.line_gaps(cursor.axis);
+    let width = gaps.0.min(gaps.1) / 2.0;
+    let color = rgba(1.0, 0.5, 0.0, 0.35);
+    let (offset_x, offset_y) = settings.grid_offset();
 
-    // Create points at which to start and end drawing the line
-    let mut sdraw = start;
-    let mut edraw = start + draw_direction;
+    let mut line_idx = cursor.index;
+    loop {
+        match cursor.axis {
+            Axis::Horizontal => {
+                let y = bounds.top() - offset_y - line_distance(gaps, line_idx as i64);
+                if y <= bounds.bottom() {
+                    break;
+                }
+                draw.rect()
+                    .x_y(bounds.x(), y)
+                    .w_h(bounds.w(), width)
+                    .color(color);
+            }
+            Axis::Vertical => {
+                let x = bounds.left() + offset_x + line_distance(gaps, line_idx as i64);
+                if x >= bounds.right() {
+                    break;
+                }
+                draw.rect()
+                    .x_y(x, bounds.y())
+                    .w_h(width, bounds.h())
+                    .color(color);
+            }
+        }
+        line_idx += len;
+    }
+}
 
-    // While the distance from `start` to `end` is longer than from `start` to `edraw`
-    while start.distance(end) > start.distance(edraw) {
-        // Draw the dash
-        draw.line().start(sdraw).end(edraw).weight(3.0);
+/// Where the point (`x`, `y`) of a pattern that fills `bounds` is in the window. The
+/// pattern is laid out like an image, with y increasing downwards from the top left, while
+/// nannou's y increases upwards from the middle.
+fn to_window(bounds: Rect, [x, y]: [f32; 2]) -> Point2 {
+    pt2(bounds.left() + x, bounds.top() - y)
+}
 
-        // Increment `sdraw` and `edraw`
-        sdraw = edraw + draw_direction;
-        edraw = sdraw + draw_direction;
+/// Draw the dashes of a square grid within `bounds`. They are the dashes that are exported
+/// (see `Pattern::styled_dashes`), with the image's downward y flipped to nannou's upward
+/// y, so the window shows the same lines with the same selectors, offsets, tiling and
+/// colors as the PNG.
+fn draw_hito_square(draw: &Draw, bounds: Rect, settings: &RectSettings) {
+    let pattern = Pattern::new(settings, bounds.w() as u32, bounds.h() as u32);
+    let to_window = |point| to_window(bounds, point);
+    for (dash, style) in pattern.styled_dashes() {
+        let stitch = Stitch::from(dash);
+        draw.line()
+            .start(to_window(stitch.start))
+            .end(to_window(stitch.end))
+            .weight(style.weight)
+            .color(stitch_color(style, &stitch, bounds));
     }
 }
 
-/// Draw horizontal dashed lines with `dash_length` dashes and `dash_length` spacing between lines.
-/// The `on_off_selectors` pair up with each line. If true, then it starts with a dash,
-/// if false it starts with a space. If the bounds go farther than the `on_off_selectors`
-/// then `idx % on_off_selectors.len()` is used to continue selecting bools from it.
-fn draw_hito_horizontal(draw: &Draw, bounds: Rect, dash_length: f32, on_off_selectors: &[bool]) {
-    // Start at the top and go down
-    let mut current_y_pos = bounds.top();
+/// Draw the three families of dashed lines of a triangular grid within `bounds`: the
+/// horizontal lines, and the lines at 60° to them running down to the right and down to
+/// the left. Each family takes its own selectors (the horizontal, vertical, and diagonal
+/// ones), and the stitches are the same as those exported (see `Pattern`).
+fn draw_hito_triangular(draw: &Draw, bounds: Rect, settings: &RectSettings) {
+    let pattern = Pattern::new(settings, bounds.w() as u32, bounds.h() as u32);
+    let to_window = |point| to_window(bounds, point);
+    for stitch in pattern.triangle_stitches() {
+        let style = settings.line_style(stitch.axis);
+        draw.line()
+            .start(to_window(stitch.start))
+            .end(to_window(stitch.end))
+            .weight(style.weight)
+            .color(stitch_color(style, &stitch, bounds));
+    }
+}
 
-    // What index of `on_off_selectors` are we on
-    let mut selector_idx = 0;
+/// Draw the dashed rings and spokes of a polar grid within `bounds`, centered on them. The
+/// rings take the horizontal selectors and the spokes the vertical ones, and the stitches
+/// are the same as those exported (see `Pattern::polar_arcs`).
+fn draw_hito_polar(draw: &Draw, bounds: Rect, settings: &RectSettings) {
+    let pattern = Pattern::new(settings, bounds.w() as u32, bounds.h() as u32);
+    let to_window = |point| to_window(bounds, point);
 
-    // Move down the window
-    while current_y_pos > bounds.bottom() {
-        let start_x = if on_off_selectors[selector_idx % on_off_selectors.len()] {
-            bounds.left()
-        } else {
-            bounds.left() + dash_length
+    let style = settings.line_style(Axis::Horizontal);
+    for arc in pattern.polar_arcs() {
+        // Each point takes its own color, so a gradient runs smoothly around the rings
+        let color = |point| {
+            line_color(
+                style,
+                gradient_position(Axis::Horizontal, point, bounds.w(), bounds.h()),
+            )
         };
-        // bump `selector_idx`
-        selector_idx += 1;
-
-        // Draw the line
-        draw_dashed_line(
-            draw,
-            pt2(start_x, current_y_pos),
-            pt2(bounds.right(), current_y_pos),
-            dash_length,
-        );
+        draw.polyline()
+            .weight(style.weight)
+            .points_colored(arc.points().map(|point| (to_window(point), color(point))));
+    }
 
-        // Update y position
-        current_y_pos -= dash_length;
+    let style = settings.line_style(Axis::Vertical);
+    for stitch in pattern.polar_spokes() {
+        draw.line()
+            .start(to_window(stitch.start))
+            .end(to_window(stitch.end))
+            .weight(style.weight)
+            .color(stitch_color(style, &stitch, bounds));
     }
 }
 
-/// Draw vertical dashed lines with `dash_length` dashes and `dash_length` spacing between lines.
-/// The `on_off_selectors` pair up with each line. If true, then it starts with a dash,
-/// if false it starts with a space. If the bounds go farther than the `on_off_selectors`
-/// then `idx % on_off_selectors.len()` is used to continue selecting bools from it.
-fn draw_hito_vertical(draw: &Draw, bounds: Rect, dash_length: f32, on_off_selectors: &[bool]) {
-    // Move from left to right
-    let mut current_x_pos = bounds.left();
+/// Render the `layers` at `size` (adjusted by the first layer's `grid_lock`) and write them
+/// to `path` as a PNG. Like `write_layers_png`, this fails if there are no `layers`.
+fn create_image_with_pattern(
+    size: (u32, u32),
+    layers: &[RectSettings],
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let first = layers.first().ok_or(LoadError::BadField("layers"))?;
+    let (width, height) = first.export_size(size.0, size.1);
 
-    // What index of `on_off_selectors` are we on
-    let mut selector_idx = 0;
+    write_layers_png(path, layers, width, height)
+}
 
-    // Move right across the window
-    while current_x_pos < bounds.right() {
-        let start_y = if on_off_selectors[selector_idx % on_off_selectors.len()] {
-            bounds.top()
-        } else {
-            bounds.top() + dash_length
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selector_cursor_wraps_at_edges() {
+        let mut settings = RectSettings::default().with_spacing(10.0).build();
+        settings.set_selectors(Axis::Horizontal, vec![false; 4]);
+        settings.set_selectors(Axis::Vertical, vec![false; 2]);
+        let mut cursor = SelectorCursor {
+            axis: Axis::Horizontal,
+            index: 0,
         };
-        // bump `selector_idx`
-        selector_idx += 1;
-
-        // Draw the line
-        draw_dashed_line(
-            draw,
-            pt2(current_x_pos, start_y),
-            pt2(current_x_pos, bounds.bottom()),
-            dash_length,
-        );
+        let at = |axis, index| SelectorCursor { axis, index };
 
-        // Update x position
-        current_x_pos += dash_length;
-    }
-}
-
-/*
-I think that perhaps we could calculate the vertical spacing between lines, figure out
-the equation of the line in the form `x + y + value = 0`, then use the `.x()` and
-`.y()` methods to set the line.
-An alternative option is to define a vector normal to the line, pointing in the "direction
-of movement" as we draw new lines. Then we just draw really long lines. This would be
-painfully in-elegant, but it would work for now. Would also want to check in on how drawing
-really big things can affect performance.
-This function also needs to make sure that the ends of each dash line up with the ends
-of dashes at different angles. I.e. All three sets of lines: horizontal, 60 deg, and 120
-deg need to meet at individual single points.
-Maybe could I draw a grid of points, then for each point, draw three lines, rotated 0, 60,
-and 120 degrees. (Need to figure out how far from point to edge of box). Start in top-left
-corner (that's an aribtrary choice), create a vector that goes down and right at 60deg.
-Every `dash_length`, draw a line that extends to the edges, one at 0, 60, and 120 deg.
-If this doesn't cover the whole window, maybe do this again, but from another corner?
-Problem with this is controlling when the dashes start. Perhaps could have vector from
-top left corner. Travel `dash_length` along that vector, then travel as far as necessary
-at 90 deg until an edge is hit. Then draw the dashed line.
-*/
-
-/// Draw angled dashed lines with `dash_length` dashes and `dash_length` spacing between lines.
-/// The `on_off_selectors` pair up with each line. If true, then it starts with a dash,
-/// if false it starts with a space. If the bounds go farther than the `on_off_selectors`
-/// then `idx % on_off_selectors.len()` is used to continue selecting bools from it.
-/// `degs` is the number of degrees the lines should be angled: [0, 180] where 0 is horizontal,
-/// 90 is vertical, and 180 is once again horizontal.
-fn draw_hito_angled(
-    draw: &Draw,
-    bounds: Rect,
-    dash_length: f32,
-    on_off_selectors: &[bool],
-    degs: f32,
-) {
-    /*
-    Start at the top left corner. If we're at angle `degs`, then the
-    distance down the wall is `dash_length / cosd(degs)`. Go down the wall until the next
-    line would start below the bottom of the window. Calculate the ratio of how far
-    the next line would have start relative to the distance to the bottom of the window.
-    Take the remaining amount, and move the correct amount horizontally.
-    `dash_length / sind(degs)`
-    */
-    let rads = degs.to_radians();
-    let max_line_length = bounds.bottom_left().distance(bounds.top_right());
-    let draw_direction_upwards = vec2(rads.cos(), rads.sin()).normalize() * max_line_length;
-
-    let vert_dist = vec2(0.0, (dash_length / rads.cos()).abs());
-    let horz_dist = vec2((dash_length / rads.sin()).abs(), 0.0);
-
-    // Go down the left side, drawing lines every `dash_length / cosd(degs)`, until we hit
-    // the bottom of the window
-    let mut spoint = bounds.top_left();
-    while spoint.y > bounds.bottom() {
-        // Create the ending points
-        let end_upwards = spoint + draw_direction_upwards;
-        let end_downwards = spoint - draw_direction_upwards;
-
-        // Draw the line in both directions
-        // Yes, this is doing extra work, but I'm feeling lazy and don't want to figure out
-        // how to do it properly
-        draw_dashed_line(draw, spoint, end_upwards, dash_length);
-        draw_dashed_line(draw, spoint, end_downwards, dash_length);
-
-        // Move down
-        spoint -= vert_dist;
-    }
-
-    // Find the new starting point along the bottom of the window
-    let ratio_downward = spoint.distance(bounds.bottom_left()) / vert_dist.length();
-    assert!(
-        ratio_downward < 1.0,
-        "The starting draw point is below the bottom left corner"
-    );
-    let ratio_right = 1.0 - ratio_downward;
-    spoint = bounds.bottom_right() + (horz_dist * ratio_right);
+        // Left of the first selector is the last one, and right of the last is the first
+        assert!(cursor.move_with_key(Key::Left, &settings));
+        assert_eq!(cursor, at(Axis::Horizontal, 3));
+        assert!(cursor.move_with_key(Key::Right, &settings));
+        assert_eq!(cursor, at(Axis::Horizontal, 0));
 
-    while spoint.x < bounds.right() {
-        // Create the ending points
-        let end_upwards = spoint + draw_direction_upwards;
-        let end_downwards = spoint - draw_direction_upwards;
+        // Moving between the rows keeps the index, clamped to the length of the other row
+        cursor.index = 3;
+        assert!(cursor.move_with_key(Key::Down, &settings));
+        assert_eq!(cursor, at(Axis::Vertical, 1));
+        assert!(cursor.move_with_key(Key::Right, &settings));
+        assert_eq!(cursor, at(Axis::Vertical, 0));
+        // Up from the top row wraps to the bottom row too
+        assert!(cursor.move_with_key(Key::Up, &settings));
+        assert_eq!(cursor, at(Axis::Horizontal, 0));
+        assert!(cursor.move_with_key(Key::Up, &settings));
+        assert_eq!(cursor, at(Axis::Vertical, 0));
 
-        // Draw the line in both directions
-        // Yes, this is doing extra work, but I'm feeling lazy and don't want to figure out
-        // how to do it properly
-        draw_dashed_line(draw, spoint, end_upwards, dash_length);
-        draw_dashed_line(draw, spoint, end_downwards, dash_length);
+        // Other keys leave it where it is
+        assert!(!cursor.move_with_key(Key::Space, &settings));
+        assert_eq!(cursor, at(Axis::Vertical, 0));
+    }
 
-        // Move right
-        spoint += horz_dist;
+    #[test]
+    fn render_rejects_bad_spacing() {
+        for spacing in ["0", "-3", "0.5", "NaN", "inf", "wide"] {
+            let args = [
+                "--render",
+                "--spacing",
+                spacing,
+                "--out",
+                "never_written.png",
+            ]
+            .map(String::from);
+            let e = render_from_args(&args).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!("Invalid value for --spacing: {spacing}")
+            );
+        }
+        assert!(!Path::new("never_written.png").exists());
     }
-}
 
-/// Starting at `point`, and moving in `direction`, within the given `bounds`, what is the
-/// point on the bounds that we hit? If the point is not in the bounds, `None` is returned
-fn calc_wall_intersection(point: Point2, direction: Vec2, bounds: &Rect) -> Option<Point2> {
-    // If not in the bounds, return None
-    if !bounds.contains(point) {
-        return None;
+    #[test]
+    fn grid_cell_under_point() {
+        let mut settings = RectSettings::default().with_spacing(10.0).build();
+        let bounds = Rect::from_w_h(100.0, 80.0);
+        let at = |settings: &RectSettings, x, y| {
+            // Measured from the top left, like the image
+            let cell = GridCell::at(settings, bounds, pt2(bounds.left() + x, bounds.top() - y));
+            (cell.col, cell.row)
+        };
+        assert_eq!(at(&settings, 15.0, 25.0), (1, 2));
+        // A point on a line is in the cell after it
+        assert_eq!(at(&settings, 20.0, 0.0), (2, 0));
+
+        // With the grid shifted and the gaps alternating between 10 and 4
+        settings.spacing_alt = Some(4.0);
+        settings.offset_x = 3.0;
+        assert_eq!(at(&settings, 15.0, 12.0), (1, 1));
+        assert_eq!(at(&settings, 2.0, 15.0), (-1, 2));
     }
 
-    None
+    #[test]
+    fn live_view_lines_take_same_selectors_as_export() {
+        let settings = RectSettings::default()
+            .with_spacing(10.0)
+            .with_seeds(42, 7)
+            .build();
+        let bounds = Rect::from_w_h(60.0, 40.0);
+        // The dashes the window draws, as `draw_hito_square` draws them
+        let window_dashes: Vec<_> = Pattern::new(&settings, 60, 40)
+            .styled_dashes()
+            .into_iter()
+            .map(|(dash, _)| {
+                let stitch = Stitch::from(dash);
+                (
+                    dash.axis,
+                    to_window(bounds, stitch.start),
+                    to_window(bounds, stitch.end),
+                )
+            })
+            .collect();
+
+        // Counting down from the top of the window, each horizontal line starts with a dash
+        // at the left edge exactly when its selector is true, like the exported image
+        for line in 0..4 {
+            let y = bounds.top() - line as f32 * 10.0;
+            let starts_on = window_dashes.iter().any(|&(axis, start, end)| {
+                axis == Axis::Horizontal && start.y == y && start.x.min(end.x) == bounds.left()
+            });
+            assert_eq!(
+                starts_on, settings.horz_selectors[line],
+                "horizontal line {line}"
+            );
+        }
+        // And counting right from the left, each vertical line starts with a dash at the top
+        for line in 0..6 {
+            let x = bounds.left() + line as f32 * 10.0;
+            let starts_on = window_dashes.iter().any(|&(axis, start, end)| {
+                axis == Axis::Vertical && start.x == x && start.y.max(end.y) == bounds.top()
+            });
+            assert_eq!(
+                starts_on, settings.vert_selectors[line],
+                "vertical line {line}"
+            );
+        }
+
+        // The exported image agrees, at the middle of the first cell of those lines
+        let pixels = render_layers_rgba(60, 40, std::slice::from_ref(&settings));
+        let is_black = |x: usize, y: usize| pixels[(y * 60 + x) * 4] == 0;
+        for line in 0..4 {
+            assert_eq!(is_black(5, line * 10), settings.horz_selectors[line]);
+        }
+        for line in 0..6 {
+            assert_eq!(is_black(line * 10, 5), settings.vert_selectors[line]);
+        }
+    }
+
+    #[test]
+    fn exports_to_missing_folder_fail_with_message() {
+        let path = Path::new("no_such_folder/pattern.png");
+        let layers = [RectSettings::default().with_spacing(10.0).build()];
+        let e = save_png_result((20, 20), &layers, path).unwrap_err();
+        assert!(
+            e.starts_with("Failed to save no_such_folder/pattern.png: "),
+            "{e}"
+        );
+
+        // GIFs and contact sheets send theirs back from the background
+        let settings = layers[0].clone();
+        let receiver = begin_export("contact sheet", path.to_path_buf(), move |path| {
+            write_contact_sheet(path, &settings, 2, 16)
+        });
+        let e = receiver.recv().unwrap().unwrap_err();
+        assert!(e.starts_with("Failed to create contact sheet: "), "{e}");
+    }
 }
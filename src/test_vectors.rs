@@ -0,0 +1,137 @@
+//! Reference patterns for the tests: settings, the first selectors their seeds generate,
+//! and the first dashes they put on the first lines. The dashes are small enough to check
+//! by hand from the selectors, so if generating selectors or placing dashes ever changes,
+//! these say exactly what changed.
+
+use crate::{selectors_from_bits, Axis, Dash, Pattern, RectSettings};
+
+/// The size of image the dashes of each vector are laid out in
+const VECTOR_SIZE: (u32, u32) = (60, 40);
+
+/// How many dashes along each axis a vector checks
+const VECTOR_LEN: usize = 3;
+
+/// The settings of a reference pattern, its first 8 horizontal and vertical selectors (as
+/// bits, see `selectors_to_bits`), and the first `VECTOR_LEN` dashes along the vertical
+/// lines and then along the horizontal lines, at `VECTOR_SIZE`
+fn test_vectors() -> Vec<(RectSettings, [&'static str; 2], Vec<Dash>)> {
+    vec![
+        // The first vertical lines start with gaps, and the first one's last dash ends on the
+        // bottom edge. The top horizontal line starts with a dash.
+        (
+            RectSettings::default()
+                .with_spacing(10.0)
+                .with_seeds(1, 2)
+                .build(),
+            ["10001110", "00111110"],
+            vec![
+                vertical(0.0, 10.0, 20.0),
+                vertical(0.0, 30.0, 40.0),
+                vertical(10.0, 10.0, 20.0),
+                horizontal(0.0, 0.0, 10.0),
+                horizontal(0.0, 20.0, 30.0),
+                horizontal(0.0, 40.0, 50.0),
+            ],
+        ),
+        // A low bias gives mostly false selectors, so most lines start with a gap. The top
+        // line's third dash ends on the right edge.
+        (
+            RectSettings::default()
+                .with_spacing(10.0)
+                .with_seeds(3, 4)
+                .with_bias(0.25)
+                .build(),
+            ["00000010", "10000001"],
+            vec![
+                vertical(0.0, 0.0, 10.0),
+                vertical(0.0, 20.0, 30.0),
+                vertical(10.0, 10.0, 20.0),
+                horizontal(0.0, 10.0, 20.0),
+                horizontal(0.0, 30.0, 40.0),
+                horizontal(0.0, 50.0, 60.0),
+            ],
+        ),
+        // The cells alternate between 8 and 4 pixels, so a line starting with a gap has 4
+        // pixel dashes after the 8 pixel cells, and one starting with a dash has 8 pixel ones
+        (
+            {
+                let mut settings = RectSettings::default().with_spacing(8.0).with_seeds(5, 6);
+                settings.spacing_alt = Some(4.0);
+                settings.build()
+            },
+            ["10101011", "00001000"],
+            vec![
+                vertical(0.0, 8.0, 12.0),
+                vertical(0.0, 20.0, 24.0),
+                vertical(0.0, 32.0, 36.0),
+                horizontal(0.0, 0.0, 8.0),
+                horizontal(0.0, 12.0, 20.0),
+                horizontal(0.0, 24.0, 32.0),
+            ],
+        ),
+        // The grid is shifted 3 pixels right, so the top line's first dash, in the cell
+        // before its first vertical line, is cut short by the left edge
+        (
+            {
+                let mut settings = RectSettings::default().with_spacing(10.0).with_seeds(7, 8);
+                settings.brick = true;
+                settings.offset_x = 3.0;
+                settings.build()
+            },
+            ["00110001", "10111001"],
+            vec![
+                vertical(3.0, 0.0, 10.0),
+                vertical(3.0, 20.0, 30.0),
+                vertical(13.0, 10.0, 20.0),
+                horizontal(0.0, 0.0, 3.0),
+                horizontal(0.0, 13.0, 23.0),
+                horizontal(0.0, 33.0, 43.0),
+            ],
+        ),
+    ]
+}
+
+fn vertical(x: f32, start: f32, end: f32) -> Dash {
+    Dash {
+        axis: Axis::Vertical,
+        line_pos: x,
+        start,
+        end,
+    }
+}
+
+fn horizontal(y: f32, start: f32, end: f32) -> Dash {
+    Dash {
+        axis: Axis::Horizontal,
+        line_pos: y,
+        start,
+        end,
+    }
+}
+
+#[test]
+fn test_vectors_match() {
+    for (settings, [horz_bits, vert_bits], expected_dashes) in test_vectors() {
+        // `build` generated the selectors from the seeds
+        assert_eq!(
+            settings.horz_selectors[..8],
+            selectors_from_bits(horz_bits).unwrap()
+        );
+        assert_eq!(
+            settings.vert_selectors[..8],
+            selectors_from_bits(vert_bits).unwrap()
+        );
+
+        let (width, height) = VECTOR_SIZE;
+        let pattern = Pattern::new(&settings, width, height);
+        let dashes = [Axis::Vertical, Axis::Horizontal]
+            .into_iter()
+            .flat_map(|axis| {
+                pattern
+                    .dashes()
+                    .filter(move |dash| dash.axis == axis)
+                    .take(VECTOR_LEN)
+            });
+        assert_eq!(dashes.collect::<Vec<_>>(), expected_dashes);
+    }
+}